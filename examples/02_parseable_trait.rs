@@ -25,6 +25,7 @@ impl csv_parser::CSVParseable for Person {
     }
 }
 
+#[allow(clippy::needless_late_init)]
 fn main() {
     let csv_string = String::from("first name,last name,DOB,email\njames,bob,00/00/2101,james@bob.com\ncatherine,crack,00/01/2102,catherine@crack.com\n");
     let doc: csv_parser::CSVDocument;
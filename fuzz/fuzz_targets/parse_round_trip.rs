@@ -0,0 +1,53 @@
+// Parses arbitrary bytes and checks that whatever the parser accepts survives being written and
+// parsed again. The first byte picks the dialect so every supported dialect gets exercised.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use csv_parser::{CSVDocument, CSVLineTerminator, CSVParseOptions, CSVWriteOptions};
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+
+    let flags = data[0];
+    let mut options = CSVParseOptions::new();
+
+    options.dialect.delimiter = [',', ';', '\t', '|'][(flags & 0b11) as usize];
+    options.dialect.quote = if flags & 0b100 == 0 { '"' } else { '\'' };
+    options.dialect.has_header = flags & 0b1000 == 0;
+    options.dialect.line_terminator = match (flags >> 4) & 0b11 {
+        0 | 1 => CSVLineTerminator::LF,
+        2 => CSVLineTerminator::CRLF,
+        _ => CSVLineTerminator::CR,
+    };
+
+    if flags & 0b100_0000 != 0 {
+        options.dialect.escape = Some('\\');
+    }
+
+    if flags & 0b1000_0000 != 0 {
+        options.dialect.comment = Some('#');
+    }
+
+    let doc = match CSVDocument::parse_bytes(&data[1..], &options) {
+        Ok(doc) => doc,
+        Err(_) => return,
+    };
+
+    let mut write_options = CSVWriteOptions::new();
+    write_options.dialect = options.dialect.clone();
+
+    let written = doc.to_string_with_options(&write_options);
+    let reparsed = match CSVDocument::parse_string_with_options(&written, &options) {
+        Ok(doc) => doc,
+        Err(e) => panic!("{:?} could not be parsed again: {}", written, e),
+    };
+
+    assert_eq!(reparsed.header.cells, doc.header.cells);
+    assert_eq!(reparsed.contents.len(), doc.contents.len());
+
+    for i in 0..doc.contents.len() {
+        assert_eq!(reparsed.contents[i].cells, doc.contents[i].cells);
+    }
+});
@@ -0,0 +1,537 @@
+use crate::csv_diff::change_log_header;
+use crate::csv_diff::CSVDiff;
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+const NO_KEYS: &str = "A changeset needs at least one key column.";
+
+// One change to a document. Rows are found by the values of the changeset's key columns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CSVChange {
+    Insert(CSVRow), // A row with its cells in the order of the target header.
+    Delete {
+        key: Vec<String>,
+        old: Option<CSVRow>, // When set the row has to still hold these cells.
+    },
+    Update {
+        key: Vec<String>,
+        column: String,
+        old: String,
+        new: String,
+    },
+}
+
+// Why a change could not be applied. change is the index of the change, or None when the changeset
+// as a whole does not fit the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVConflict {
+    pub change: Option<usize>,
+    pub message: &'static str,
+}
+
+// A list of changes that can be written to a file and applied to a document later, so incremental
+// updates can be shipped instead of whole files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVChangeset {
+    pub keys: Vec<String>,
+    pub changes: Vec<CSVChange>,
+}
+
+impl CSVChangeset {
+    // The keys have to name at least one column, which apply checks.
+    pub fn new(keys: &[&str]) -> CSVChangeset {
+        return CSVChangeset {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            changes: vec![],
+        };
+    }
+
+    // Builds the changes that turn the old document of a diff into the new one. Header changes are
+    // not represented, inserted rows get the columns of the old header and the update of a column
+    // missing from the old header is skipped.
+    pub fn from_diff(diff: &CSVDiff) -> CSVChangeset {
+        let mut changeset = CSVChangeset {
+            keys: diff.keys.clone(),
+            changes: vec![],
+        };
+
+        let key_of = |row: &CSVRow| -> Vec<String> {
+            return diff
+                .keys
+                .iter()
+                .map(|key| cell(&diff.old_header, row, key))
+                .collect();
+        };
+
+        for change in diff.modified_rows.iter() {
+            for cell in change.changes.iter() {
+                changeset.changes.push(CSVChange::Update {
+                    key: change.key.clone(),
+                    column: cell.column.clone(),
+                    old: cell.old.clone(),
+                    new: cell.new.clone(),
+                });
+            }
+        }
+
+        for row in diff.removed_rows.iter() {
+            changeset.changes.push(CSVChange::Delete {
+                key: key_of(row),
+                old: Some(row.clone()),
+            });
+        }
+
+        for row in diff.added_rows.iter() {
+            let cells = diff
+                .old_header
+                .cells
+                .iter()
+                .map(|name| cell(&diff.new_header, row, name))
+                .collect();
+
+            changeset.changes.push(CSVChange::Insert(CSVRow {
+                cells,
+                ..CSVRow::new()
+            }));
+        }
+
+        return changeset;
+    }
+
+    // Applies every change in order. Nothing is changed unless all of them apply, otherwise every
+    // conflict found is returned. An update conflicts when the cell no longer holds the old value, a
+    // delete when the row is missing or differs from the old row, and an insert when its key is taken.
+    pub fn apply(&self, doc: &mut CSVDocument) -> Result<(), Vec<CSVConflict>> {
+        if self.keys.is_empty() {
+            return Err(vec![CSVConflict {
+                change: None,
+                message: NO_KEYS,
+            }]);
+        }
+
+        let keys = match doc.find_columns(&self.keys) {
+            Ok(keys) => keys,
+            Err(e) => {
+                return Err(vec![CSVConflict {
+                    change: None,
+                    message: e,
+                }])
+            }
+        };
+
+        // The changes are made to a copy of the rows, where deleted rows are left as None so the
+        // positions in the key index stay valid.
+        let mut rows: Vec<Option<CSVRow>> = doc.contents.iter().cloned().map(Some).collect();
+        let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+        let mut conflicts: Vec<CSVConflict> = vec![];
+
+        let key_of = |row: &CSVRow| -> Vec<String> {
+            return keys
+                .iter()
+                .map(|index| row.cells.get(*index).cloned().unwrap_or_default())
+                .collect();
+        };
+
+        for (position, row) in doc.contents.iter().enumerate() {
+            if index.insert(key_of(row), position).is_some() {
+                return Err(vec![CSVConflict {
+                    change: None,
+                    message: "The key columns do not identify the rows uniquely.",
+                }]);
+            }
+        }
+
+        for (i, change) in self.changes.iter().enumerate() {
+            let result = match change {
+                CSVChange::Insert(row) => match index.entry(key_of(row)) {
+                    _ if row.len() != doc.header.len() => {
+                        Err("The amount of cells in the row was invalid.")
+                    }
+                    Entry::Occupied(_) => Err("A row with that key already exists."),
+                    Entry::Vacant(entry) => {
+                        entry.insert(rows.len());
+                        rows.push(Some(row.clone()));
+                        Ok(())
+                    }
+                },
+                CSVChange::Delete { key, old } => match index.get(key).copied() {
+                    Some(position)
+                        if old.as_ref().is_some_and(|old| {
+                            rows[position]
+                                .as_ref()
+                                .is_some_and(|row| old.cells != row.cells)
+                        }) =>
+                    {
+                        Err("The row does not match the old row.")
+                    }
+                    Some(position) => {
+                        index.remove(key);
+                        rows[position] = None;
+                        Ok(())
+                    }
+                    None => Err("No row has that key."),
+                },
+                CSVChange::Update {
+                    key,
+                    column,
+                    old,
+                    new,
+                } => match (index.get(key).copied(), doc.column_index(column)) {
+                    (None, _) => Err("No row has that key."),
+                    (_, None) => Err("The column does not exist."),
+                    (Some(position), Some(column)) => {
+                        let row = rows[position].as_mut().unwrap();
+
+                        if row.cells[column] != *old {
+                            Err("The cell does not match the old value.")
+                        } else if keys.contains(&column) {
+                            // Changing a key column moves the row to its new key.
+                            let mut changed = row.clone();
+                            changed.cells[column] = new.clone();
+                            let changed_key = key_of(&changed);
+
+                            if changed_key != *key && index.contains_key(&changed_key) {
+                                Err("A row with that key already exists.")
+                            } else {
+                                index.remove(key);
+                                index.insert(changed_key, position);
+                                *row = changed;
+                                Ok(())
+                            }
+                        } else {
+                            row.cells[column] = new.clone();
+                            Ok(())
+                        }
+                    }
+                },
+            };
+
+            if let Err(message) = result {
+                conflicts.push(CSVConflict {
+                    change: Some(i),
+                    message,
+                });
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
+        doc.contents = rows.into_iter().flatten().collect();
+
+        return Ok(());
+    }
+
+    // Writes the changeset in the same layout as CSVDiff::to_change_log, with the header change, the
+    // key columns, column, old and new. Inserted and deleted rows are written as a line of CSV.
+    pub fn to_document(&self) -> Result<CSVDocument, &'static str> {
+        let header = match change_log_header(&self.keys) {
+            Ok(header) => header,
+            Err(e) => return Err(e),
+        };
+
+        let mut doc = CSVDocument {
+            header,
+            ..CSVDocument::new()
+        };
+
+        for change in self.changes.iter() {
+            let (name, key, column, old, new) = match change {
+                CSVChange::Insert(row) => {
+                    let key = self.keys.iter().map(|_| String::new()).collect();
+                    ("insert", key, String::new(), String::new(), row.to_string())
+                }
+                CSVChange::Delete { key, old } => {
+                    let old = old.as_ref().map(|row| row.to_string()).unwrap_or_default();
+                    ("delete", key.clone(), String::new(), old, String::new())
+                }
+                CSVChange::Update {
+                    key,
+                    column,
+                    old,
+                    new,
+                } => (
+                    "update",
+                    key.clone(),
+                    column.clone(),
+                    old.clone(),
+                    new.clone(),
+                ),
+            };
+
+            let mut cells = vec![name.to_string()];
+            cells.extend(key);
+            cells.extend([column, old, new]);
+
+            doc.contents.push(CSVRow {
+                cells,
+                ..CSVRow::new()
+            });
+        }
+
+        return Ok(doc);
+    }
+
+    pub fn to_string(&self) -> Result<String, &'static str> {
+        return self.to_document().map(|doc| doc.to_string());
+    }
+
+    // Reads a changeset written by to_string or to_document.
+    pub fn parse_string(input: &String) -> Result<CSVChangeset, &'static str> {
+        return match CSVDocument::parse_string(input) {
+            Ok(doc) => CSVChangeset::from_document(&doc),
+            Err(e) => Err(e),
+        };
+    }
+
+    pub fn from_document(doc: &CSVDocument) -> Result<CSVChangeset, &'static str> {
+        let header = &doc.header.cells;
+        let width = header.len();
+
+        if width < 4 || header[0] != "change" || header[width - 3..] != ["column", "old", "new"] {
+            return Err("The document is not a changeset.");
+        }
+
+        if width == 4 {
+            return Err(NO_KEYS);
+        }
+
+        if let Err(e) = change_log_header(&header[1..width - 3]) {
+            return Err(e);
+        }
+
+        let mut changeset = CSVChangeset {
+            keys: header[1..width - 3].to_vec(),
+            changes: vec![],
+        };
+
+        for row in doc.contents.iter() {
+            if row.len() != width {
+                return Err("The amount of cells in the row was invalid.");
+            }
+
+            let key = row.cells[1..width - 3].to_vec();
+            let column = row.cells[width - 3].clone();
+            let old = row.cells[width - 2].clone();
+            let new = row.cells[width - 1].clone();
+
+            let change = match row.cells[0].as_str() {
+                "insert" => match CSVRow::parse_line_new(&new) {
+                    Ok(row) => CSVChange::Insert(row),
+                    Err(e) => return Err(e),
+                },
+                "delete" if old.is_empty() => CSVChange::Delete { key, old: None },
+                "delete" => match CSVRow::parse_line_new(&old) {
+                    Ok(row) => CSVChange::Delete {
+                        key,
+                        old: Some(row),
+                    },
+                    Err(e) => return Err(e),
+                },
+                "update" => CSVChange::Update {
+                    key,
+                    column,
+                    old,
+                    new,
+                },
+                _ => return Err("The change is not insert, delete or update."),
+            };
+
+            changeset.changes.push(change);
+        }
+
+        return Ok(changeset);
+    }
+}
+
+fn cell(header: &CSVRow, row: &CSVRow, name: &String) -> String {
+    return match header.cells.iter().position(|cell| cell == name) {
+        Some(index) => row.cells[index].clone(),
+        None => String::new(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(string: &str) -> CSVDocument {
+        return CSVDocument::parse_string(&string.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    fn base() -> CSVDocument {
+        return parse("id,name,city\n1,ann,Auckland\n2,bob,Sydney\n3,cat,Perth\n");
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut doc = base();
+        let mut changeset = CSVChangeset::new(&["id"]);
+
+        changeset.changes.push(CSVChange::Update {
+            key: vec!["3".to_string()],
+            column: "city".to_string(),
+            old: "Perth".to_string(),
+            new: "Hobart, TAS".to_string(),
+        });
+        changeset.changes.push(CSVChange::Delete {
+            key: vec!["2".to_string()],
+            old: None,
+        });
+        changeset.changes.push(CSVChange::Insert(
+            CSVRow::parse_line_new(&"4,dan,Nelson".to_string()).unwrap(),
+        ));
+
+        changeset.apply(&mut doc).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "id,name,city\n1,ann,Auckland\n3,cat,\"Hobart, TAS\"\n4,dan,Nelson\n"
+        );
+
+        // Updating a key column moves the row to its new key, freeing the old one.
+        let mut changeset = CSVChangeset::new(&["id"]);
+
+        changeset.changes.push(CSVChange::Update {
+            key: vec!["1".to_string()],
+            column: "id".to_string(),
+            old: "1".to_string(),
+            new: "5".to_string(),
+        });
+        changeset.changes.push(CSVChange::Insert(
+            CSVRow::parse_line_new(&"1,eve,Napier".to_string()).unwrap(),
+        ));
+        changeset.changes.push(CSVChange::Delete {
+            key: vec!["5".to_string()],
+            old: None,
+        });
+
+        changeset.apply(&mut doc).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "id,name,city\n3,cat,\"Hobart, TAS\"\n4,dan,Nelson\n1,eve,Napier\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_conflicts() {
+        let mut doc = base();
+        let mut changeset = CSVChangeset::new(&["id"]);
+
+        changeset.changes.push(CSVChange::Update {
+            key: vec!["1".to_string()],
+            column: "city".to_string(),
+            old: "Wellington".to_string(),
+            new: "Nelson".to_string(),
+        });
+        changeset.changes.push(CSVChange::Update {
+            key: vec!["2".to_string()],
+            column: "city".to_string(),
+            old: "Sydney".to_string(),
+            new: "Darwin".to_string(),
+        });
+        changeset.changes.push(CSVChange::Delete {
+            key: vec!["9".to_string()],
+            old: None,
+        });
+        changeset.changes.push(CSVChange::Insert(
+            CSVRow::parse_line_new(&"3,cat,Perth".to_string()).unwrap(),
+        ));
+
+        assert_eq!(
+            changeset.apply(&mut doc),
+            Err(vec![
+                CSVConflict {
+                    change: Some(0),
+                    message: "The cell does not match the old value.",
+                },
+                CSVConflict {
+                    change: Some(2),
+                    message: "No row has that key.",
+                },
+                CSVConflict {
+                    change: Some(3),
+                    message: "A row with that key already exists.",
+                },
+            ])
+        );
+
+        // Nothing is applied when there is a conflict.
+        assert_eq!(doc.to_string(), base().to_string());
+
+        assert_eq!(
+            CSVChangeset::new(&["email"]).apply(&mut doc),
+            Err(vec![CSVConflict {
+                change: None,
+                message: "The column does not exist.",
+            }])
+        );
+
+        let mut duplicated = parse("id,name\n1,ann\n1,bob\n");
+        assert_eq!(
+            CSVChangeset::new(&["id"]).apply(&mut duplicated),
+            Err(vec![CSVConflict {
+                change: None,
+                message: "The key columns do not identify the rows uniquely.",
+            }])
+        );
+
+        assert_eq!(
+            CSVChangeset::new(&[]).apply(&mut doc),
+            Err(vec![CSVConflict {
+                change: None,
+                message: "A changeset needs at least one key column.",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_diff_round_trip() {
+        let old = base();
+        let new = parse("id,name,city\n1,ann,Auckland\n3,cat,\"Hobart, TAS\"\n4,dan,Nelson\n");
+
+        let changeset = CSVChangeset::from_diff(&old.diff(&new, &["id"]).unwrap());
+        let serialized = changeset.to_string().unwrap();
+
+        assert_eq!(
+            serialized,
+            "change,id,column,old,new\n\
+             update,3,city,Perth,\"Hobart, TAS\"\n\
+             delete,2,,\"2,bob,Sydney\",\n\
+             insert,,,,\"4,dan,Nelson\"\n"
+        );
+
+        let parsed = CSVChangeset::parse_string(&serialized).unwrap();
+        assert_eq!(parsed.keys, changeset.keys);
+        assert_eq!(parsed.changes.len(), 3);
+
+        let mut doc = old.clone();
+        parsed.apply(&mut doc).unwrap();
+        assert_eq!(doc.to_string(), new.to_string());
+
+        // Applying it a second time conflicts with the changes already made.
+        assert_eq!(parsed.apply(&mut doc).err().map(|c| c.len()), Some(3));
+
+        assert_eq!(
+            CSVChangeset::parse_string(&"a,b\n1,2\n".to_string()).err(),
+            Some("The document is not a changeset.")
+        );
+        assert_eq!(
+            CSVChangeset::parse_string(&"change,column,old,new\n".to_string()).err(),
+            Some("A changeset needs at least one key column.")
+        );
+
+        assert_eq!(
+            CSVChangeset::new(&["new"]).to_string().err(),
+            Some("A key column is named change, column, old or new.")
+        );
+        assert_eq!(
+            CSVChangeset::parse_string(&"change,old,column,old,new\n".to_string()).err(),
+            Some("A key column is named change, column, old or new.")
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+
+// Column operations address columns by their header name and keep the header and every row in step,
+// so they need every row to be as wide as the header.
+impl CSVDocument {
+    // Returns the index of the first column with the name.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        return self.header.cells.iter().position(|cell| cell == name);
+    }
+
+    pub fn add_column(&mut self, name: &str, values: Vec<String>) -> Result<(), &'static str> {
+        return self.insert_column_at(self.header.len(), name, values);
+    }
+
+    // Adds a column computed from each row.
+    pub fn add_column_with<F>(&mut self, name: &str, f: F) -> Result<(), &'static str>
+    where
+        F: FnMut(&CSVRow) -> String,
+    {
+        let values: Vec<String> = self.contents.iter().map(f).collect();
+
+        return self.add_column(name, values);
+    }
+
+    pub fn insert_column_at(
+        &mut self,
+        index: usize,
+        name: &str,
+        values: Vec<String>,
+    ) -> Result<(), &'static str> {
+        if let Err(e) = self.check_columns() {
+            return Err(e);
+        }
+
+        if index > self.header.len() {
+            return Err("Index is too large.");
+        } else if self.column_index(name).is_some() {
+            return Err("A column with that name already exists.");
+        } else if values.len() != self.contents.len() {
+            return Err("The amount of values did not match the amount of rows.");
+        }
+
+        self.header.insert_cell(index, name.to_string());
+
+        for (row, value) in self.contents.iter_mut().zip(values) {
+            row.insert_cell(index, value);
+        }
+
+        return Ok(());
+    }
+
+    pub fn remove_column(&mut self, name: &str) -> Result<(), &'static str> {
+        let index = match self.find_column(name) {
+            Ok(index) => index,
+            Err(e) => return Err(e),
+        };
+
+        self.header.remove_cell(index);
+
+        for row in self.contents.iter_mut() {
+            row.remove_cell(index);
+        }
+
+        return Ok(());
+    }
+
+    pub fn rename_column(&mut self, name: &str, new_name: &str) -> Result<(), &'static str> {
+        let index = match self.find_column(name) {
+            Ok(index) => index,
+            Err(e) => return Err(e),
+        };
+
+        if name != new_name && self.column_index(new_name).is_some() {
+            return Err("A column with that name already exists.");
+        }
+
+        self.header.cells[index] = new_name.to_string();
+
+        return Ok(());
+    }
+
+    // Puts the columns in the given order, every column has to be named exactly once.
+    pub fn reorder_columns(&mut self, names: &[&str]) -> Result<(), &'static str> {
+        if names.len() != self.header.len() {
+            return Err("Every column has to be named exactly once.");
+        }
+
+        return self.select_columns(names);
+    }
+
+    // Keeps only the named columns, in the given order.
+    pub fn select_columns(&mut self, names: &[&str]) -> Result<(), &'static str> {
+        let mut indexes: Vec<usize> = vec![];
+
+        for name in names {
+            match self.find_column(name) {
+                Ok(index) if indexes.contains(&index) => {
+                    return Err("Every column has to be named exactly once.")
+                }
+                Ok(index) => indexes.push(index),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.header = self.header.select_cells(&indexes);
+
+        for row in self.contents.iter_mut() {
+            *row = row.select_cells(&indexes);
+        }
+
+        return Ok(());
+    }
+
+    // Looks up a column after checking the document is rectangular.
+    pub(crate) fn find_column(&self, name: &str) -> Result<usize, &'static str> {
+        if let Err(e) = self.check_columns() {
+            return Err(e);
+        }
+
+        return match self.column_index(name) {
+            Some(index) => Ok(index),
+            None => Err("The column does not exist."),
+        };
+    }
+
+    pub(crate) fn find_columns<S: AsRef<str>>(
+        &self,
+        names: &[S],
+    ) -> Result<Vec<usize>, &'static str> {
+        let mut indexes = vec![];
+
+        for name in names {
+            match self.find_column(name.as_ref()) {
+                Ok(index) => indexes.push(index),
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Ok(indexes);
+    }
+
+    pub(crate) fn check_columns(&self) -> Result<(), &'static str> {
+        if self
+            .contents
+            .iter()
+            .any(|row| row.len() != self.header.len())
+        {
+            return Err("The amount of cells in the row was invalid.");
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> CSVDocument {
+        let string: String = "name,age,country\nann,41,NZ\nbob,\"\",AU\n".to_string();
+
+        return CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    #[test]
+    fn test_add_column() {
+        let mut doc = document();
+
+        doc.add_column("id", vec!["1".to_string(), "2".to_string()])
+            .unwrap();
+        doc.add_column_with("label", |row| format!("{}-{}", row.cells[0], row.cells[2]))
+            .unwrap();
+        doc.insert_column_at(0, "first", vec!["x".to_string(), "y".to_string()])
+            .unwrap();
+
+        assert_eq!(
+            doc.to_string(),
+            "first,name,age,country,id,label\nx,ann,41,NZ,1,ann-NZ\ny,bob,\"\",AU,2,bob-AU\n"
+        );
+    }
+
+    #[test]
+    fn test_add_column_fail() {
+        let mut doc = document();
+
+        assert_eq!(
+            doc.add_column("age", vec!["1".to_string(), "2".to_string()]),
+            Err("A column with that name already exists.")
+        );
+        assert_eq!(
+            doc.add_column("id", vec!["1".to_string()]),
+            Err("The amount of values did not match the amount of rows.")
+        );
+        assert_eq!(
+            doc.insert_column_at(4, "id", vec!["1".to_string(), "2".to_string()]),
+            Err("Index is too large.")
+        );
+
+        doc.contents[0].cells.pop();
+        assert_eq!(
+            doc.remove_column("age"),
+            Err("The amount of cells in the row was invalid.")
+        );
+    }
+
+    #[test]
+    fn test_remove_and_rename_column() {
+        let mut doc = document();
+
+        doc.remove_column("name").unwrap();
+        doc.rename_column("country", "nation").unwrap();
+
+        assert_eq!(doc.remove_column("name"), Err("The column does not exist."));
+        assert_eq!(
+            doc.rename_column("age", "nation"),
+            Err("A column with that name already exists.")
+        );
+        assert_eq!(doc.to_string(), "age,nation\n41,NZ\n\"\",AU\n");
+    }
+
+    #[test]
+    fn test_reorder_and_select_columns() {
+        let mut doc = document();
+
+        doc.reorder_columns(&["country", "age", "name"]).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "country,age,name\nNZ,41,ann\nAU,\"\",bob\n"
+        );
+
+        assert_eq!(
+            doc.reorder_columns(&["country", "age"]),
+            Err("Every column has to be named exactly once.")
+        );
+        assert_eq!(
+            doc.select_columns(&["age", "age"]),
+            Err("Every column has to be named exactly once.")
+        );
+
+        doc.select_columns(&["age", "country"]).unwrap();
+        assert_eq!(doc.to_string(), "age,country\n41,NZ\n\"\",AU\n");
+    }
+}
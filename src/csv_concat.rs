@@ -0,0 +1,133 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+
+// How concat lines up documents whose headers differ.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CSVAlign {
+    Union(String), // Keep every column, filling the cells a document does not have with the value.
+    Intersect,     // Keep only the columns every document has.
+}
+
+impl CSVDocument {
+    // Appends the rows of the documents, matching columns by header name. Union keeps the columns in
+    // the order they first appear, Intersect in the order of the first document. A header with the
+    // same name twice is an error since its cells could go in either column.
+    pub fn concat(docs: &[CSVDocument], align: &CSVAlign) -> Result<CSVDocument, &'static str> {
+        let mut names: Vec<&String> = vec![];
+
+        for doc in docs {
+            if let Err(e) = doc.check_columns() {
+                return Err(e);
+            }
+
+            for (i, name) in doc.header.cells.iter().enumerate() {
+                if doc.header.cells[..i].contains(name) {
+                    return Err("A document has more than one column with the same name.");
+                } else if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        if *align == CSVAlign::Intersect {
+            names.retain(|name| docs.iter().all(|doc| doc.header.cells.contains(name)));
+        }
+
+        let fill = match align {
+            CSVAlign::Union(fill) => fill.clone(),
+            CSVAlign::Intersect => String::new(),
+        };
+
+        let mut doc = CSVDocument {
+            header: CSVRow {
+                cells: names.iter().map(|name| name.to_string()).collect(),
+                quoted: vec![false; names.len()],
+            },
+            ..CSVDocument::new()
+        };
+
+        if let Some(first) = docs.first() {
+            doc.has_bom = first.has_bom;
+            doc.encoding = first.encoding;
+        }
+
+        for source in docs {
+            let positions: Vec<Option<usize>> =
+                names.iter().map(|name| source.column_index(name)).collect();
+
+            for row in source.contents.iter() {
+                let mut aligned = CSVRow::new();
+
+                for position in positions.iter() {
+                    match position {
+                        Some(index) => {
+                            aligned.cells.push(row.cells[*index].clone());
+                            aligned.quoted.push(row.is_quoted(*index));
+                        }
+                        None => {
+                            aligned.cells.push(fill.clone());
+                            aligned.quoted.push(false);
+                        }
+                    }
+                }
+
+                doc.contents.push(aligned);
+            }
+        }
+
+        return Ok(doc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(string: &str) -> CSVDocument {
+        return CSVDocument::parse_string(&string.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    fn months() -> Vec<CSVDocument> {
+        return vec![
+            parse("id,amount\n1,10\n2,20\n"),
+            parse("id,amount,region\n3,30,NZ\n"),
+            parse("region,id\nAU,4\n"),
+        ];
+    }
+
+    #[test]
+    fn test_concat_union() {
+        let doc = CSVDocument::concat(&months(), &CSVAlign::Union("n/a".to_string())).unwrap();
+
+        assert_eq!(
+            doc.to_string(),
+            "id,amount,region\n1,10,n/a\n2,20,n/a\n3,30,NZ\n4,n/a,AU\n"
+        );
+    }
+
+    #[test]
+    fn test_concat_intersect() {
+        let doc = CSVDocument::concat(&months(), &CSVAlign::Intersect).unwrap();
+
+        assert_eq!(doc.to_string(), "id\n1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn test_concat_duplicate_columns() {
+        let mut docs = months();
+        docs.push(parse("id,id\n5,6\n"));
+
+        assert_eq!(
+            CSVDocument::concat(&docs, &CSVAlign::Intersect).err(),
+            Some("A document has more than one column with the same name.")
+        );
+        assert_eq!(
+            CSVDocument::concat(&[], &CSVAlign::Intersect)
+                .unwrap()
+                .row_count(),
+            0
+        );
+    }
+}
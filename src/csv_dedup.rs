@@ -0,0 +1,132 @@
+use crate::csv_document::CSVDocument;
+use std::collections::HashMap;
+
+// Which of a set of duplicate rows is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVKeep {
+    First,
+    Last,
+}
+
+impl CSVDocument {
+    // Removes rows whose cells all equal those of another row, returning the indexes the removed rows
+    // had before the call, in ascending order.
+    pub fn dedup(&mut self, keep: CSVKeep) -> Vec<usize> {
+        // Rows wider than the header still compare on every cell.
+        let width = self
+            .contents
+            .iter()
+            .map(|row| row.len())
+            .fold(self.header.len(), usize::max);
+        let indexes: Vec<usize> = (0..width).collect();
+
+        return self.dedup_indexes(&indexes, keep);
+    }
+
+    // Removes rows that have the same cells as another row in the named columns.
+    pub fn dedup_by_columns(
+        &mut self,
+        names: &[&str],
+        keep: CSVKeep,
+    ) -> Result<Vec<usize>, &'static str> {
+        return match self.find_columns(names) {
+            Ok(indexes) => Ok(self.dedup_indexes(&indexes, keep)),
+            Err(e) => Err(e),
+        };
+    }
+
+    fn dedup_indexes(&mut self, indexes: &[usize], keep: CSVKeep) -> Vec<usize> {
+        // Maps each key to the row that is kept for it so far.
+        let mut kept: HashMap<Vec<Option<&String>>, usize> = HashMap::new();
+        let mut removed: Vec<usize> = vec![];
+
+        for (i, row) in self.contents.iter().enumerate() {
+            let key: Vec<Option<&String>> =
+                indexes.iter().map(|index| row.cells.get(*index)).collect();
+
+            match kept.get_mut(&key) {
+                Some(previous) => match keep {
+                    CSVKeep::First => removed.push(i),
+                    CSVKeep::Last => {
+                        removed.push(*previous);
+                        *previous = i;
+                    }
+                },
+                None => {
+                    kept.insert(key, i);
+                }
+            }
+        }
+
+        removed.sort_unstable();
+
+        let mut index = 0;
+        let mut next = removed.iter().peekable();
+
+        self.contents.retain(|_| {
+            let remove = next.next_if(|i| **i == index).is_some();
+            index += 1;
+            !remove
+        });
+
+        return removed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> CSVDocument {
+        let string: String =
+            "id,name,city\n1,ann,Auckland\n2,bob,Sydney\n1,ann,Auckland\n3,ann,Perth\n2,bob,Sydney\n"
+                .to_string();
+
+        return CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    fn ids(doc: &CSVDocument) -> Vec<&str> {
+        return doc
+            .contents
+            .iter()
+            .map(|row| row.cells[0].as_str())
+            .collect();
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut doc = document();
+        assert_eq!(doc.dedup(CSVKeep::First), vec![2, 4]);
+        assert_eq!(ids(&doc), vec!["1", "2", "3"]);
+
+        let mut doc = document();
+        assert_eq!(doc.dedup(CSVKeep::Last), vec![0, 1]);
+        assert_eq!(ids(&doc), vec!["1", "3", "2"]);
+
+        assert_eq!(doc.dedup(CSVKeep::First), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_dedup_by_columns() {
+        let mut doc = document();
+        assert_eq!(
+            doc.dedup_by_columns(&["name"], CSVKeep::Last).unwrap(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(ids(&doc), vec!["3", "2"]);
+
+        let mut doc = document();
+        assert_eq!(
+            doc.dedup_by_columns(&["name", "city"], CSVKeep::First)
+                .unwrap(),
+            vec![2, 4]
+        );
+
+        assert_eq!(
+            doc.dedup_by_columns(&["country"], CSVKeep::First),
+            Err("The column does not exist.")
+        );
+    }
+}
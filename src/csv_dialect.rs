@@ -0,0 +1,63 @@
+// The sequences that can separate records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVLineTerminator {
+    LF,
+    CRLF,
+    CR,
+}
+
+impl CSVLineTerminator {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            CSVLineTerminator::LF => "\n",
+            CSVLineTerminator::CRLF => "\r\n",
+            CSVLineTerminator::CR => "\r",
+        };
+    }
+}
+
+// Which parsed cells have their leading and trailing whitespace removed. Whitespace inside quotes is
+// only removed by AllFields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVTrim {
+    None,
+    UnquotedFields,
+    AllFields,
+    HeadersOnly,
+}
+
+// Describes the flavour of CSV being read or written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVDialect {
+    pub delimiter: char,
+    pub quote: char,
+    pub has_header: bool, // When false every record is content and the header row is left empty.
+    pub line_terminator: CSVLineTerminator,
+    pub comment: Option<char>, // Lines starting with this character are comments.
+    pub trim: CSVTrim,
+    pub escape: Option<char>, // Escapes the next character instead of doubling quotes, e.g. a backslash.
+}
+
+impl CSVDialect {
+    pub fn new() -> CSVDialect {
+        return CSVDialect {
+            delimiter: ',',
+            quote: '"',
+            has_header: true,
+            line_terminator: CSVLineTerminator::LF,
+            comment: None,
+            trim: CSVTrim::None,
+            escape: None,
+        };
+    }
+}
+
+// Returns the character an escape sequence stands for, e.g. the n in \n is a new line.
+pub(crate) fn unescape(c: char) -> char {
+    return match c {
+        't' => '\t',
+        'r' => '\r',
+        'n' => '\n',
+        c => c,
+    };
+}
@@ -0,0 +1,443 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::collections::HashMap;
+
+// A cell whose value differs between the two documents.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVCellChange {
+    pub column: String,
+    pub old: String,
+    pub new: String,
+}
+
+// A row present in both documents with different cells in the columns they share.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVRowChange {
+    pub key: Vec<String>,
+    pub old: CSVRow,
+    pub new: CSVRow,
+    pub changes: Vec<CSVCellChange>,
+}
+
+// The differences between an old and a new document whose rows are matched by key columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVDiff {
+    pub keys: Vec<String>,
+    pub old_header: CSVRow,
+    pub new_header: CSVRow,
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+    pub reordered: bool, // The columns in both documents are in a different order.
+    pub added_rows: Vec<CSVRow>, // In the order of the new document.
+    pub removed_rows: Vec<CSVRow>, // In the order of the old document.
+    pub modified_rows: Vec<CSVRowChange>, // In the order of the old document.
+}
+
+impl CSVDiff {
+    pub fn is_empty(&self) -> bool {
+        return self.added_columns.is_empty()
+            && self.removed_columns.is_empty()
+            && !self.reordered
+            && self.added_rows.is_empty()
+            && self.removed_rows.is_empty()
+            && self.modified_rows.is_empty();
+    }
+
+    // Renders the diff like a unified diff of the two files. The header is shown first when it
+    // changed, followed by a hunk for every modified, removed and added row, named by its key.
+    pub fn to_unified(&self) -> String {
+        let mut str = String::from("--- old\n+++ new\n");
+
+        if self.old_header.cells != self.new_header.cells {
+            str += &format!(
+                "@@ header @@\n-{}\n+{}\n",
+                self.old_header.to_string(),
+                self.new_header.to_string()
+            );
+        }
+
+        for change in self.modified_rows.iter() {
+            str += &format!(
+                "@@ {} @@\n-{}\n+{}\n",
+                self.describe_key(&change.key),
+                change.old.to_string(),
+                change.new.to_string()
+            );
+        }
+
+        for row in self.removed_rows.iter() {
+            str += &format!(
+                "@@ {} @@\n-{}\n",
+                self.describe_key(&self.key_of(&self.old_header, row)),
+                row.to_string()
+            );
+        }
+
+        for row in self.added_rows.iter() {
+            str += &format!(
+                "@@ {} @@\n+{}\n",
+                self.describe_key(&self.key_of(&self.new_header, row)),
+                row.to_string()
+            );
+        }
+
+        return str;
+    }
+
+    // Renders the diff as a document with one change per row. The header is change, the key columns,
+    // column, old and new. Header changes come first as add_column, remove_column and
+    // reorder_columns, followed by update for each changed cell, then remove_row and add_row with the
+    // whole row written as a line of CSV.
+    pub fn to_change_log(&self) -> Result<CSVDocument, &'static str> {
+        let header = match change_log_header(&self.keys) {
+            Ok(header) => header,
+            Err(e) => return Err(e),
+        };
+
+        let mut doc = CSVDocument {
+            header,
+            ..CSVDocument::new()
+        };
+
+        let no_key = vec![String::new(); self.keys.len()];
+        let mut log = |change: &str, key: &Vec<String>, column: &str, old: String, new: String| {
+            let mut cells = vec![change.to_string()];
+            cells.extend(key.iter().cloned());
+            cells.extend([column.to_string(), old, new]);
+
+            doc.contents.push(CSVRow {
+                cells,
+                ..CSVRow::new()
+            });
+        };
+
+        for column in self.added_columns.iter() {
+            log("add_column", &no_key, column, String::new(), String::new());
+        }
+
+        for column in self.removed_columns.iter() {
+            log(
+                "remove_column",
+                &no_key,
+                column,
+                String::new(),
+                String::new(),
+            );
+        }
+
+        if self.reordered {
+            log(
+                "reorder_columns",
+                &no_key,
+                "",
+                self.old_header.to_string(),
+                self.new_header.to_string(),
+            );
+        }
+
+        for change in self.modified_rows.iter() {
+            for cell in change.changes.iter() {
+                log(
+                    "update",
+                    &change.key,
+                    &cell.column,
+                    cell.old.clone(),
+                    cell.new.clone(),
+                );
+            }
+        }
+
+        for row in self.removed_rows.iter() {
+            log(
+                "remove_row",
+                &self.key_of(&self.old_header, row),
+                "",
+                row.to_string(),
+                String::new(),
+            );
+        }
+
+        for row in self.added_rows.iter() {
+            log(
+                "add_row",
+                &self.key_of(&self.new_header, row),
+                "",
+                String::new(),
+                row.to_string(),
+            );
+        }
+
+        return Ok(doc);
+    }
+
+    fn key_of(&self, header: &CSVRow, row: &CSVRow) -> Vec<String> {
+        return self
+            .keys
+            .iter()
+            .map(
+                |key| match header.cells.iter().position(|cell| cell == key) {
+                    Some(index) => row.cells[index].clone(),
+                    None => String::new(),
+                },
+            )
+            .collect();
+    }
+
+    fn describe_key(&self, key: &[String]) -> String {
+        return self
+            .keys
+            .iter()
+            .zip(key.iter())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(" ");
+    }
+}
+
+impl CSVDocument {
+    // Compares this document, as the old version, with a new version. Rows are matched by the key
+    // columns, which have to exist in both documents and be unique within each.
+    pub fn diff(&self, new: &CSVDocument, keys: &[&str]) -> Result<CSVDiff, &'static str> {
+        if keys.is_empty() {
+            return Err("A diff needs at least one key column.");
+        }
+
+        let (old_keys, new_keys) = match (self.find_columns(keys), new.find_columns(keys)) {
+            (Ok(old_keys), Ok(new_keys)) => (old_keys, new_keys),
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        };
+
+        let old_index = match index_rows(self, &old_keys) {
+            Ok(index) => index,
+            Err(e) => return Err(e),
+        };
+
+        let new_index = match index_rows(new, &new_keys) {
+            Ok(index) => index,
+            Err(e) => return Err(e),
+        };
+
+        let old_names = &self.header.cells;
+        let new_names = &new.header.cells;
+
+        // The columns both documents share, as pairs of old and new indexes in the old order.
+        let shared: Vec<(usize, usize)> = old_names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| new_names.iter().position(|n| n == name).map(|j| (i, j)))
+            .collect();
+
+        let mut diff = CSVDiff {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            old_header: self.header.clone(),
+            new_header: new.header.clone(),
+            added_columns: new_names
+                .iter()
+                .filter(|n| !old_names.contains(n))
+                .cloned()
+                .collect(),
+            removed_columns: old_names
+                .iter()
+                .filter(|n| !new_names.contains(n))
+                .cloned()
+                .collect(),
+            reordered: shared.windows(2).any(|pair| pair[0].1 > pair[1].1),
+            added_rows: vec![],
+            removed_rows: vec![],
+            modified_rows: vec![],
+        };
+
+        for row in self.contents.iter() {
+            let key: Vec<&String> = old_keys.iter().map(|k| &row.cells[*k]).collect();
+
+            let other = match new_index.get(&key) {
+                Some(other) => &new.contents[*other],
+                None => {
+                    diff.removed_rows.push(row.clone());
+                    continue;
+                }
+            };
+
+            let changes: Vec<CSVCellChange> = shared
+                .iter()
+                .filter(|(i, j)| row.cells[*i] != other.cells[*j])
+                .map(|(i, j)| CSVCellChange {
+                    column: old_names[*i].clone(),
+                    old: row.cells[*i].clone(),
+                    new: other.cells[*j].clone(),
+                })
+                .collect();
+
+            if !changes.is_empty() {
+                diff.modified_rows.push(CSVRowChange {
+                    key: key.into_iter().cloned().collect(),
+                    old: row.clone(),
+                    new: other.clone(),
+                    changes,
+                });
+            }
+        }
+
+        for row in new.contents.iter() {
+            let key: Vec<&String> = new_keys.iter().map(|k| &row.cells[*k]).collect();
+
+            if !old_index.contains_key(&key) {
+                diff.added_rows.push(row.clone());
+            }
+        }
+
+        return Ok(diff);
+    }
+}
+
+// The header of a change log, shared with CSVChangeset. A key column with the name of one of the
+// fixed columns would make the header ambiguous.
+pub(crate) fn change_log_header(keys: &[String]) -> Result<CSVRow, &'static str> {
+    let fixed = ["change", "column", "old", "new"];
+
+    if keys.iter().any(|key| fixed.contains(&key.as_str())) {
+        return Err("A key column is named change, column, old or new.");
+    }
+
+    let mut header = vec!["change".to_string()];
+    header.extend(keys.iter().cloned());
+    header.extend(fixed[1..].iter().map(|s| s.to_string()));
+
+    return Ok(CSVRow {
+        cells: header,
+        ..CSVRow::new()
+    });
+}
+
+// Maps the key of every row to its index.
+fn index_rows<'a>(
+    doc: &'a CSVDocument,
+    keys: &[usize],
+) -> Result<HashMap<Vec<&'a String>, usize>, &'static str> {
+    let mut index = HashMap::new();
+
+    for (i, row) in doc.contents.iter().enumerate() {
+        let key: Vec<&String> = keys.iter().map(|k| &row.cells[*k]).collect();
+
+        if index.insert(key, i).is_some() {
+            return Err("The key columns do not identify the rows uniquely.");
+        }
+    }
+
+    return Ok(index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(string: &str) -> CSVDocument {
+        return CSVDocument::parse_string(&string.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    fn old() -> CSVDocument {
+        return parse("id,name,city,age\n1,ann,Auckland,41\n2,bob,Sydney,29\n3,cat,Perth,35\n");
+    }
+
+    fn new() -> CSVDocument {
+        return parse(
+            "id,city,name,email\n1,Auckland,ann,a@x.nz\n3,Hobart,cat,c@x.au\n4,Nelson,dan,\n",
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff = old().diff(&new(), &["id"]).unwrap();
+
+        assert_eq!(diff.added_columns, vec!["email"]);
+        assert_eq!(diff.removed_columns, vec!["age"]);
+        assert!(diff.reordered);
+        assert_eq!(diff.removed_rows[0].cells, vec!["2", "bob", "Sydney", "29"]);
+        assert_eq!(diff.added_rows[0].cells, vec!["4", "Nelson", "dan", ""]);
+        assert_eq!(diff.modified_rows.len(), 1);
+        assert_eq!(diff.modified_rows[0].key, vec!["3"]);
+        assert_eq!(
+            diff.modified_rows[0].changes,
+            vec![CSVCellChange {
+                column: "city".to_string(),
+                old: "Perth".to_string(),
+                new: "Hobart".to_string(),
+            }]
+        );
+
+        assert!(old().diff(&old(), &["id"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_errors() {
+        assert_eq!(
+            old().diff(&new(), &["age"]).err(),
+            Some("The column does not exist.")
+        );
+        assert_eq!(
+            old().diff(&parse("id,name\n1,a\n1,b\n"), &["id"]).err(),
+            Some("The key columns do not identify the rows uniquely.")
+        );
+
+        let mut ragged = parse("id,name\n1,a\n");
+        ragged.contents[0].cells.pop();
+        assert_eq!(
+            parse("id,name\n1,a\n").diff(&ragged, &["id"]).err(),
+            Some("The amount of cells in the row was invalid.")
+        );
+
+        assert_eq!(
+            old().diff(&new(), &[]).err(),
+            Some("A diff needs at least one key column.")
+        );
+    }
+
+    #[test]
+    fn test_diff_unified() {
+        assert_eq!(
+            old().diff(&new(), &["id"]).unwrap().to_unified(),
+            "--- old\n+++ new\n\
+             @@ header @@\n-id,name,city,age\n+id,city,name,email\n\
+             @@ id=3 @@\n-3,cat,Perth,35\n+3,Hobart,cat,c@x.au\n\
+             @@ id=2 @@\n-2,bob,Sydney,29\n\
+             @@ id=4 @@\n+4,Nelson,dan,\n"
+        );
+
+        // Quoting alone is not a change.
+        let quoted = parse("\"id\",name\n1,a\n");
+        assert_eq!(
+            quoted
+                .diff(&parse("id,name\n1,a\n"), &["id"])
+                .unwrap()
+                .to_unified(),
+            "--- old\n+++ new\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_change_log() {
+        assert_eq!(
+            old()
+                .diff(&new(), &["id"])
+                .unwrap()
+                .to_change_log()
+                .unwrap()
+                .to_string(),
+            "change,id,column,old,new\n\
+             add_column,,email,,\n\
+             remove_column,,age,,\n\
+             reorder_columns,,,\"id,name,city,age\",\"id,city,name,email\"\n\
+             update,3,city,Perth,Hobart\n\
+             remove_row,2,,\"2,bob,Sydney,29\",\n\
+             add_row,4,,,\"4,Nelson,dan,\"\n"
+        );
+
+        let doc = parse("old,name\n1,a\n");
+        assert_eq!(
+            doc.diff(&doc, &["old"]).unwrap().to_change_log().err(),
+            Some("A key column is named change, column, old or new.")
+        );
+    }
+}
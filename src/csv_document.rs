@@ -1,9 +1,33 @@
+use crate::csv_encoding::CSVEncoding;
+use crate::csv_options::CSVLimits;
+use crate::csv_options::CSVParseOptions;
+use crate::csv_options::CSVWidthPolicy;
+use crate::csv_options::CSVWriteOptions;
+use crate::csv_options::UTF8_BOM;
 use crate::csv_row::CSVParseable;
 use crate::csv_row::CSVRow;
+use crate::csv_row::LineWriter;
+use crate::csv_tokenizer::CSVToken;
+use crate::csv_tokenizer::CSVTokenizer;
+use std::ops::Bound;
+use std::ops::Range;
+use std::ops::RangeBounds;
 
+// A comment line kept from the input so it can be written back out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVComment {
+    pub position: usize, // The number of records, including the header, written before the comment.
+    pub text: String,    // The comment without its prefix character.
+}
+
+#[derive(Clone, Debug)]
 pub struct CSVDocument {
     pub header: CSVRow,
     pub contents: Vec<CSVRow>,
+    pub has_bom: bool, // Set when the parsed input started with a byte order mark.
+    pub encoding: CSVEncoding, // The encoding the document was decoded from.
+    pub comments: Vec<CSVComment>, // Comment lines, only filled when the parser is asked to preserve them.
+    pub width_policy: CSVWidthPolicy, // How insert_row and replace_row treat rows of the wrong width.
 }
 
 impl CSVDocument {
@@ -11,6 +35,10 @@ impl CSVDocument {
         return CSVDocument {
             header: CSVRow::new(),
             contents: vec![],
+            has_bom: false,
+            encoding: CSVEncoding::Utf8,
+            comments: vec![],
+            width_policy: CSVWidthPolicy::Strict,
         };
     }
 
@@ -18,6 +46,8 @@ impl CSVDocument {
         return self.contents[row_index].cells[cell_index].clone();
     }
 
+    // Appends without applying the width policy, since add_row cannot report a rejected row. Use
+    // insert_row at row_count() to append a row checked against the policy.
     pub fn add_row(&mut self, row: CSVRow) {
         self.contents.push(row);
     }
@@ -32,6 +62,109 @@ impl CSVDocument {
         return Ok(());
     }
 
+    pub fn insert_row(&mut self, index: usize, row: CSVRow) -> Result<(), &'static str> {
+        if index > self.contents.len() {
+            return Err("Index is too large.");
+        }
+
+        match self.fit_row(row) {
+            Ok(row) => self.contents.insert(index, row),
+            Err(e) => return Err(e),
+        }
+
+        return Ok(());
+    }
+
+    // Replaces the row at the index, returning the row that was there.
+    pub fn replace_row(&mut self, index: usize, row: CSVRow) -> Result<CSVRow, &'static str> {
+        if index >= self.contents.len() {
+            return Err("Index is too large.");
+        }
+
+        return match self.fit_row(row) {
+            Ok(row) => Ok(std::mem::replace(&mut self.contents[index], row)),
+            Err(e) => Err(e),
+        };
+    }
+
+    pub fn remove_rows<R: RangeBounds<usize>>(&mut self, range: R) -> Result<(), &'static str> {
+        return match self.drain(range) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+    }
+
+    // Removes the rows in the range and returns them.
+    pub fn drain<R: RangeBounds<usize>>(
+        &mut self,
+        range: R,
+    ) -> Result<std::vec::Drain<'_, CSVRow>, &'static str> {
+        return match self.resolve_range(range) {
+            Ok(range) => Ok(self.contents.drain(range)),
+            Err(e) => Err(e),
+        };
+    }
+
+    // Keeps only the rows the predicate returns true for.
+    pub fn retain<F: FnMut(&CSVRow) -> bool>(&mut self, f: F) {
+        self.contents.retain(f);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.contents.truncate(len);
+    }
+
+    // Applies the width policy to a row that is about to be added. The width comes from the header,
+    // or from the first row when there is no header, the same way the parser checks widths.
+    fn fit_row(&self, mut row: CSVRow) -> Result<CSVRow, &'static str> {
+        let width = if !self.header.is_empty() {
+            self.header.len()
+        } else {
+            match self.contents.first() {
+                Some(first) => first.len(),
+                None => return Ok(row),
+            }
+        };
+
+        let pad = self.width_policy == CSVWidthPolicy::Pad
+            || self.width_policy == CSVWidthPolicy::PadOrTruncate;
+
+        if self.width_policy == CSVWidthPolicy::Allow || row.len() == width {
+            return Ok(row);
+        } else if row.len() < width && pad {
+            row.cells.resize(width, String::new());
+        } else if row.len() > width && self.width_policy == CSVWidthPolicy::PadOrTruncate {
+            row.cells.truncate(width);
+            row.quoted.truncate(width);
+        } else {
+            return Err("The amount of cells in the row was invalid.");
+        }
+
+        return Ok(row);
+    }
+
+    fn resolve_range<R: RangeBounds<usize>>(&self, range: R) -> Result<Range<usize>, &'static str> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(end) => end + 1,
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => self.contents.len(),
+        };
+
+        if end > self.contents.len() {
+            return Err("Index is too large.");
+        } else if start > end {
+            return Err("The start of the range is after its end.");
+        }
+
+        return Ok(start..end);
+    }
+
     pub fn row_count(&self) -> usize {
         return self.contents.len();
     }
@@ -49,107 +182,209 @@ impl CSVDocument {
         return Ok(items);
     }
 
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
-        let mut str = self.header.to_string();
-        str.push('\n');
+        return self.to_string_with_options(&CSVWriteOptions::new());
+    }
+
+    pub fn to_string_with_options(&self, options: &CSVWriteOptions) -> String {
+        let dialect = &options.dialect;
+        let terminator = dialect.line_terminator.as_str();
+        let writer = LineWriter::new(&self.header, options);
+        let mut str = writer.bom();
 
-        for i in 0..self.contents.len() {
-            str += &self.contents[i].to_string();
-            str.push('\n');
+        let mut records: Vec<&CSVRow> = vec![];
+
+        if dialect.has_header {
+            records.push(&self.header);
         }
 
-        return str;
-    }
+        records.extend(self.contents.iter());
 
-    pub fn parse_string(input: &String) -> Result<CSVDocument, &'static str> {
-        let mut header_row = CSVRow::new();
-        let mut header_row_set = false;
-        let mut contents_rows = vec![];
-
-        let characters: Vec<char> = input.chars().collect();
-        let mut i = 0;
-        let mut in_quotes = false;
-        let mut line = String::new();
-
-        while i < characters.len() {
-            if characters[i] == '"' && !in_quotes {
-                in_quotes = true;
-            } else if characters[i] == '"' && in_quotes {
-                if i + 1 >= characters.len() {
-                    in_quotes = false;
-                } else {
-                    let p = characters[i + 1];
+        // Comments are only written when the dialect has a prefix character for them.
+        let mut comments = self.comments.iter().peekable();
 
-                    if p != '\0' && p != '"' {
-                        in_quotes = false;
-                    } else if p == '"' {
-                        i += 1;
-                        line.push('"');
-                    }
+        for i in 0..=records.len() {
+            if let Some(comment_char) = dialect.comment {
+                while let Some(comment) = comments.next_if(|c| c.position <= i) {
+                    str.push(comment_char);
+                    str.push_str(&comment.text);
+                    str.push_str(terminator);
                 }
             }
 
-            if characters[i] == '\n' && !in_quotes {
-                if !header_row_set {
-                    let res = header_row.parse_line(&line);
-                    match res {
-                        Err(e) => return Err(e),
-                        Ok(_) => (),
-                    };
-
-                    line = String::new();
-                    header_row_set = true;
+            if i < records.len() {
+                if dialect.has_header && i == 0 {
+                    str += &writer.header(records[i]);
                 } else {
-                    let res = CSVRow::parse_line_new(&line);
+                    str += &writer.row(records[i]);
+                }
+            }
+        }
+
+        return str;
+    }
 
-                    let current_row;
+    pub fn to_bytes(&self, options: &CSVWriteOptions) -> Result<Vec<u8>, &'static str> {
+        return options
+            .encoding
+            .encode(&self.to_string_with_options(options));
+    }
 
-                    match res {
-                        Err(e) => return Err(e),
-                        Ok(row) => current_row = row,
-                    }
+    pub fn parse_bytes(
+        input: &[u8],
+        options: &CSVParseOptions,
+    ) -> Result<CSVDocument, &'static str> {
+        // Checked before decoding so an oversized input is never copied.
+        if CSVLimits::exceeds(options.limits.max_total_bytes, input.len()) {
+            return Err(CSVLimits::TOTAL_BYTES_EXCEEDED);
+        }
 
-                    if current_row.len() != header_row.len() {
-                        return Err("The amount of cells in the row was invalid.");
-                    } else {
-                        contents_rows.push(current_row);
-                    }
+        let encoding = match options.encoding {
+            Some(encoding) => encoding,
+            None => CSVEncoding::detect(input),
+        };
 
-                    line = String::new();
-                }
-            } else {
-                line.push(characters[i]);
+        let text = match encoding.decode(input) {
+            Ok(text) => text,
+            Err(e) => return Err(e),
+        };
+
+        return match CSVDocument::parse_text(&text, options) {
+            Ok(mut doc) => {
+                doc.encoding = encoding;
+                Ok(doc)
             }
+            Err(e) => Err(e),
+        };
+    }
+
+    pub fn parse_string(input: &String) -> Result<CSVDocument, &'static str> {
+        return CSVDocument::parse_string_with_options(input, &CSVParseOptions::new());
+    }
 
-            i += 1;
+    #[allow(clippy::ptr_arg)]
+    pub fn parse_string_with_options(
+        input: &String,
+        options: &CSVParseOptions,
+    ) -> Result<CSVDocument, &'static str> {
+        if CSVLimits::exceeds(options.limits.max_total_bytes, input.len()) {
+            return Err(CSVLimits::TOTAL_BYTES_EXCEEDED);
         }
 
-        if line != String::new() {
-            if !header_row_set {
-                let res = header_row.parse_line(&line);
-                match res {
-                    Err(e) => return Err(e),
-                    Ok(_) => (),
-                };
-            } else {
-                let res = CSVRow::parse_line_new(&line);
+        return CSVDocument::parse_text(input, options);
+    }
+
+    // Parses decoded text without the total size check, which callers make on their own input since
+    // decoding single byte encodings can make the text longer than the bytes it came from.
+    fn parse_text(input: &str, options: &CSVParseOptions) -> Result<CSVDocument, &'static str> {
+        let mut doc = CSVDocument::new();
+        let mut characters = input.chars().peekable();
 
-                match res {
+        // The byte order mark is not part of the first cell so skip over it.
+        if characters.peek() == Some(&UTF8_BOM) {
+            doc.has_bom = true;
+            characters.next();
+        }
+
+        let mut tokenizer = CSVTokenizer::with_limits(&options.dialect, &options.limits);
+        let mut width: Option<usize> = None;
+
+        for c in characters {
+            match tokenizer.push(c) {
+                Ok(Some(token)) => match doc.add_token(token, options, &mut width) {
+                    Ok(_) => (),
                     Err(e) => return Err(e),
-                    Ok(row) => contents_rows.push(row),
+                },
+                Ok(None) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        match tokenizer.finish() {
+            Ok(Some(token)) => match doc.add_token(token, options, &mut width) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            },
+            Ok(None) => (),
+            Err(e) => return Err(e),
+        }
+
+        return Ok(doc);
+    }
+
+    // Adds a parsed line to the document. The header, or the first record when there is none,
+    // decides how wide the document is.
+    pub(crate) fn add_token(
+        &mut self,
+        token: CSVToken,
+        options: &CSVParseOptions,
+        width: &mut Option<usize>,
+    ) -> Result<(), &'static str> {
+        let has_header = options.dialect.has_header;
+
+        match token {
+            CSVToken::Comment(text) => {
+                if options.preserve_comments {
+                    let header_count = (has_header && width.is_some()) as usize;
+
+                    self.comments.push(CSVComment {
+                        position: header_count + self.contents.len(),
+                        text,
+                    });
+                }
+            }
+            CSVToken::EmptyLine => {
+                // A blank line is either dropped or kept as a row without any cells.
+                if !options.skip_empty_lines && (width.is_some() || !has_header) {
+                    return self.push_parsed_row(CSVRow::new(), options);
+                }
+            }
+            CSVToken::Record(row) => {
+                let is_header = has_header && width.is_none();
+
+                match *width {
+                    None => *width = Some(row.len()),
+                    Some(w) => {
+                        if row.len() != w {
+                            return Err("The amount of cells in the row was invalid.");
+                        }
+                    }
+                }
+
+                if is_header {
+                    self.header = row;
+                } else {
+                    return self.push_parsed_row(row, options);
                 }
             }
         }
-        return Ok(CSVDocument {
-            header: header_row,
-            contents: contents_rows,
-        });
+
+        return Ok(());
+    }
+
+    // Kept blank lines count as rows for the row limit as well.
+    fn push_parsed_row(
+        &mut self,
+        row: CSVRow,
+        options: &CSVParseOptions,
+    ) -> Result<(), &'static str> {
+        if CSVLimits::exceeds(options.limits.max_rows, self.contents.len() + 1) {
+            return Err(CSVLimits::ROWS_EXCEEDED);
+        }
+
+        self.contents.push(row);
+
+        return Ok(());
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::csv_dialect::CSVTrim;
+    use crate::csv_options::CSVSanitize;
+
     #[test]
     fn test_parse_string_1() {
         let string: String = "cats,dogs,cars,humans".to_string();
@@ -260,8 +495,10 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![],
+            ..CSVDocument::new()
         };
         assert_eq!(doc.to_string(), "name,dob,location\n");
     }
@@ -275,6 +512,7 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![CSVRow {
                 cells: vec![
@@ -282,7 +520,9 @@ mod tests {
                     String::from("14/03/2000"),
                     String::from("NYC"),
                 ],
+                ..CSVRow::new()
             }],
+            ..CSVDocument::new()
         };
         assert_eq!(doc.to_string(), "name,dob,location\njames,14/03/2000,NYC\n");
     }
@@ -296,6 +536,7 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![CSVRow {
                 cells: vec![
@@ -303,7 +544,9 @@ mod tests {
                     String::from("14/03/2000"),
                     String::from("\"Jersey\""),
                 ],
+                ..CSVRow::new()
             }],
+            ..CSVDocument::new()
         };
         assert_eq!(
             doc.to_string(),
@@ -320,6 +563,7 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![CSVRow {
                 cells: vec![
@@ -327,7 +571,9 @@ mod tests {
                     String::from("14/03/2000"),
                     String::from("\"Jersey\""),
                 ],
+                ..CSVRow::new()
             }],
+            ..CSVDocument::new()
         };
         assert_eq!(doc.row_count(), 1);
     }
@@ -341,6 +587,7 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![CSVRow {
                 cells: vec![
@@ -348,7 +595,9 @@ mod tests {
                     String::from("14/03/2000"),
                     String::from("\"Jersey\""),
                 ],
+                ..CSVRow::new()
             }],
+            ..CSVDocument::new()
         };
         assert_eq!(doc.row_count(), 1);
         let row = CSVRow {
@@ -357,6 +606,7 @@ mod tests {
                 String::from("14/03/2000"),
                 String::from("\"Jersey\""),
             ],
+            ..CSVRow::new()
         };
         doc.add_row(row);
         assert_eq!(doc.row_count(), 2);
@@ -372,6 +622,7 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![CSVRow {
                 cells: vec![
@@ -379,7 +630,9 @@ mod tests {
                     String::from("14/03/2000"),
                     String::from("\"Jersey\""),
                 ],
+                ..CSVRow::new()
             }],
+            ..CSVDocument::new()
         };
         assert_eq!(doc.row_count(), 1);
         let row = CSVRow {
@@ -388,12 +641,13 @@ mod tests {
                 String::from("14/03/2000"),
                 String::from("\"Jersey\""),
             ],
+            ..CSVRow::new()
         };
         doc.add_row(row);
         assert_eq!(doc.row_count(), 2);
         match doc.remove_row(3) {
             Ok(_) => (),
-            Err(e) => panic!(e),
+            Err(e) => panic!("{}", e),
         }
     }
 
@@ -406,6 +660,7 @@ mod tests {
                     String::from("dob"),
                     String::from("location"),
                 ],
+                ..CSVRow::new()
             },
             contents: vec![CSVRow {
                 cells: vec![
@@ -413,7 +668,9 @@ mod tests {
                     String::from("14/03/2000"),
                     String::from("\"Jersey\""),
                 ],
+                ..CSVRow::new()
             }],
+            ..CSVDocument::new()
         };
         assert_eq!(doc.row_count(), 1);
         let row = CSVRow {
@@ -422,13 +679,529 @@ mod tests {
                 String::from("14/03/2000"),
                 String::from("\"Jersey\""),
             ],
+            ..CSVRow::new()
         };
         doc.add_row(row);
         assert_eq!(doc.row_count(), 2);
         match doc.remove_row(0) {
             Ok(_) => (),
-            Err(e) => panic!(e),
+            Err(e) => panic!("{}", e),
         }
         assert_eq!(doc.row_count(), 1);
     }
+
+    fn row(cells: &[&str]) -> CSVRow {
+        return CSVRow {
+            cells: cells.iter().map(|c| c.to_string()).collect(),
+            ..CSVRow::new()
+        };
+    }
+
+    #[test]
+    fn test_insert_and_replace_row() {
+        let string: String = "a,b\n1,2\n".to_string();
+        let mut doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        doc.insert_row(0, row(&["0", "1"])).unwrap();
+        doc.insert_row(2, row(&["2", "3"])).unwrap();
+        assert_eq!(
+            doc.insert_row(4, row(&["4", "5"])),
+            Err("Index is too large.")
+        );
+        assert_eq!(
+            doc.insert_row(0, row(&["0"])),
+            Err("The amount of cells in the row was invalid.")
+        );
+
+        assert_eq!(
+            doc.replace_row(1, row(&["x", "y"])).unwrap().cells,
+            vec!["1", "2"]
+        );
+        assert_eq!(
+            doc.replace_row(3, row(&["x", "y"])),
+            Err("Index is too large.")
+        );
+        assert_eq!(doc.to_string(), "a,b\n0,1\nx,y\n2,3\n");
+    }
+
+    #[test]
+    fn test_width_policy() {
+        let mut doc: CSVDocument = CSVDocument {
+            header: row(&["a", "b"]),
+            ..CSVDocument::new()
+        };
+
+        doc.width_policy = CSVWidthPolicy::Pad;
+        doc.insert_row(0, row(&["1"])).unwrap();
+        assert_eq!(
+            doc.insert_row(0, row(&["1", "2", "3"])),
+            Err("The amount of cells in the row was invalid.")
+        );
+
+        doc.width_policy = CSVWidthPolicy::PadOrTruncate;
+        doc.insert_row(1, row(&["1", "2", "3"])).unwrap();
+
+        doc.width_policy = CSVWidthPolicy::Allow;
+        doc.insert_row(2, row(&["1", "2", "3"])).unwrap();
+
+        assert_eq!(doc.to_string(), "a,b\n1,\n1,2\n1,2,3\n");
+
+        // add_row is exempt from the policy.
+        doc.width_policy = CSVWidthPolicy::Strict;
+        doc.add_row(row(&["1"]));
+        assert_eq!(doc.contents[3].cells, vec!["1"]);
+    }
+
+    #[test]
+    fn test_remove_rows() {
+        let string: String = "a\n0\n1\n2\n3\n4\n5\n".to_string();
+        let mut doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        let drained: Vec<Vec<String>> = doc.drain(4..).unwrap().map(|row| row.cells).collect();
+        assert_eq!(drained, vec![vec!["4"], vec!["5"]]);
+
+        assert_eq!(doc.remove_rows(..=4), Err("Index is too large."));
+        doc.remove_rows(1..=1).unwrap();
+        doc.retain(|row| row.cells[0] != "2");
+        assert_eq!(doc.to_string(), "a\n0\n3\n");
+
+        doc.truncate(1);
+        assert_eq!(doc.to_string(), "a\n0\n");
+    }
+
+    #[test]
+    fn test_parse_string_bom() {
+        let string: String = "\u{feff}id,name\n1,james\n".to_string();
+        let doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert!(doc.has_bom);
+        assert_eq!(doc.header.cells, vec!["id", "name"]);
+        assert_eq!(doc.contents[0].cells, vec!["1", "james"]);
+    }
+
+    #[test]
+    fn test_parse_string_no_bom() {
+        let string: String = "id,name\n1,james\n".to_string();
+        let doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert!(!doc.has_bom);
+        assert_eq!(doc.header.cells, vec!["id", "name"]);
+    }
+
+    #[test]
+    fn test_to_string_with_bom() {
+        let doc: CSVDocument = CSVDocument {
+            header: CSVRow {
+                cells: vec![String::from("id"), String::from("name")],
+                ..CSVRow::new()
+            },
+            contents: vec![],
+            ..CSVDocument::new()
+        };
+        let mut options = CSVWriteOptions::new();
+
+        assert_eq!(doc.to_string_with_options(&options), "id,name\n");
+
+        options.bom = true;
+        assert_eq!(doc.to_string_with_options(&options), "\u{feff}id,name\n");
+    }
+
+    #[test]
+    fn test_parse_bytes_detected() {
+        let bytes: Vec<u8> = vec![
+            0xFF, 0xFE, b'i', 0, b'd', 0, b',', 0, b'n', 0, b'\n', 0, b'1', 0, b',', 0, 0xE9, 0,
+        ];
+        let doc: CSVDocument = CSVDocument::parse_bytes(&bytes, &CSVParseOptions::new())
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert!(doc.has_bom);
+        assert_eq!(doc.encoding, CSVEncoding::Utf16LE);
+        assert_eq!(doc.header.cells, vec!["id", "n"]);
+        assert_eq!(doc.contents[0].cells, vec!["1", "\u{e9}"]);
+    }
+
+    #[test]
+    fn test_parse_bytes_declared() {
+        let mut options = CSVParseOptions::new();
+        options.encoding = Some(CSVEncoding::Latin1);
+
+        let doc: CSVDocument = CSVDocument::parse_bytes(b"name\ncaf\xe9\n\x80", &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.encoding, CSVEncoding::Latin1);
+        assert_eq!(doc.contents[0].cells, vec!["caf\u{e9}"]);
+        assert_eq!(doc.contents[1].cells, vec!["\u{80}"]);
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let doc: CSVDocument = CSVDocument {
+            header: CSVRow {
+                cells: vec![String::from("caf\u{e9}")],
+                ..CSVRow::new()
+            },
+            contents: vec![],
+            ..CSVDocument::new()
+        };
+        let mut options = CSVWriteOptions::new();
+        options.encoding = CSVEncoding::Windows1252;
+
+        assert_eq!(doc.to_bytes(&options), Ok(b"caf\xe9\n".to_vec()));
+
+        options.encoding = CSVEncoding::Utf16BE;
+        options.bom = true;
+        assert_eq!(
+            doc.to_bytes(&options),
+            Ok(vec![
+                0xFE, 0xFF, 0, b'c', 0, b'a', 0, b'f', 0, 0xE9, 0, b'\n'
+            ])
+        );
+
+        // Single byte encodings have no byte order mark, so none is written.
+        options.encoding = CSVEncoding::Latin1;
+        assert_eq!(doc.to_bytes(&options), Ok(b"caf\xe9\n".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_string_comments_skipped() {
+        let string: String = "# generated\nid,name\n# \"odd quote\n1,a\n2,b\n".to_string();
+        let mut options = CSVParseOptions::new();
+        options.dialect.comment = Some('#');
+
+        let doc: CSVDocument = CSVDocument::parse_string_with_options(&string, &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.header.cells, vec!["id", "name"]);
+        assert_eq!(doc.contents.len(), 2);
+        assert_eq!(doc.contents[1].cells, vec!["2", "b"]);
+        assert!(doc.comments.is_empty());
+    }
+
+    #[test]
+    fn test_parse_string_comments_preserved() {
+        let string: String = "# generated\nid,name\n1,a\n# note\n\n2,b\n#end\n".to_string();
+        let mut options = CSVParseOptions::new();
+        options.dialect.comment = Some('#');
+        options.preserve_comments = true;
+        options.skip_empty_lines = false;
+
+        let doc: CSVDocument = CSVDocument::parse_string_with_options(&string, &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.contents.len(), 3);
+        assert!(doc.contents[1].is_empty());
+        assert_eq!(
+            doc.comments,
+            vec![
+                CSVComment {
+                    position: 0,
+                    text: String::from(" generated")
+                },
+                CSVComment {
+                    position: 2,
+                    text: String::from(" note")
+                },
+                CSVComment {
+                    position: 4,
+                    text: String::from("end")
+                },
+            ]
+        );
+
+        let mut write_options = CSVWriteOptions::new();
+        write_options.dialect.comment = Some('#');
+        assert_eq!(doc.to_string_with_options(&write_options), string);
+
+        // Without a comment character the comments are left out.
+        assert_eq!(doc.to_string(), "id,name\n1,a\n\n2,b\n");
+    }
+
+    #[test]
+    fn test_parse_string_empty_lines() {
+        let string: String = "a,b\n\n1,2\n\n".to_string();
+        let doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(doc.contents.len(), 1);
+
+        let mut options = CSVParseOptions::new();
+        options.skip_empty_lines = false;
+
+        let doc: CSVDocument = CSVDocument::parse_string_with_options(&string, &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.contents.len(), 3);
+        assert!(doc.contents[0].is_empty());
+        assert_eq!(doc.contents[1].cells, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_parse_string_trim_headers_only() {
+        let string: String = "id , name\n1, james \n".to_string();
+        let mut options = CSVParseOptions::new();
+        options.dialect.trim = CSVTrim::HeadersOnly;
+
+        let doc: CSVDocument = CSVDocument::parse_string_with_options(&string, &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.header.cells, vec!["id", "name"]);
+        assert_eq!(doc.contents[0].cells, vec!["1", " james "]);
+    }
+
+    #[test]
+    fn test_parse_string_backslash_escapes() {
+        let string: String = "id,note\n1,line\\\none\n2,\\\"quoted\n".to_string();
+        let mut options = CSVParseOptions::new();
+        options.dialect.escape = Some('\\');
+
+        let doc: CSVDocument = CSVDocument::parse_string_with_options(&string, &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.contents.len(), 2);
+        assert_eq!(doc.contents[0].cells, vec!["1", "line\none"]);
+        assert_eq!(doc.contents[1].cells, vec!["2", "\"quoted"]);
+    }
+
+    #[test]
+    fn test_parse_string_trailing_empty() {
+        let string: String = "a,b,c\n1,2,\n,\"\",\n".to_string();
+        let doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(doc.contents.len(), 2);
+        assert_eq!(doc.contents[0].cells, vec!["1", "2", ""]);
+        assert_eq!(doc.contents[1].nullable_cells()[0], None);
+        assert_eq!(doc.contents[1].nullable_cells()[1], Some(&String::new()));
+        assert_eq!(doc.to_string(), string);
+    }
+
+    #[test]
+    fn test_to_string_sanitize() {
+        let string: String = "name,amount,note\n=HYPERLINK(x),-5,@me\nbob,=1+1,-\n".to_string();
+        let doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        let mut options = CSVWriteOptions::new();
+        options.sanitize = CSVSanitize::Prefix;
+        options.numeric_columns = vec!["amount".to_string()];
+
+        assert_eq!(
+            doc.to_string_with_options(&options),
+            "name,amount,note\n'=HYPERLINK(x),-5,'@me\nbob,'=1+1,'-\n"
+        );
+
+        // Only finite numbers without surrounding whitespace are exempt.
+        let mut doc = doc;
+        doc.contents = vec![row(&["a", "\t-5", ""]), row(&["b", "-inf", "-NaN"])];
+        assert_eq!(
+            doc.to_string_with_options(&options),
+            "name,amount,note\na,\"'\t-5\",\nb,'-inf,'-NaN\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_string_limits() {
+        let string: String = "a,b
+1,22
+3,4
+"
+        .to_string();
+        let parse = |limits: CSVLimits| {
+            let mut options = CSVParseOptions::new();
+            options.limits = limits;
+            CSVDocument::parse_string_with_options(&string, &options).err()
+        };
+
+        let mut limits = CSVLimits::new();
+        assert_eq!(parse(limits.clone()), None);
+
+        limits.max_field_size = Some(1);
+        assert_eq!(parse(limits.clone()), Some(CSVLimits::FIELD_SIZE_EXCEEDED));
+
+        limits = CSVLimits::new();
+        limits.max_record_size = Some(4);
+        assert_eq!(parse(limits.clone()), Some(CSVLimits::RECORD_SIZE_EXCEEDED));
+
+        limits = CSVLimits::new();
+        limits.max_columns = Some(1);
+        assert_eq!(parse(limits.clone()), Some(CSVLimits::COLUMNS_EXCEEDED));
+
+        limits = CSVLimits::new();
+        limits.max_rows = Some(1);
+        assert_eq!(parse(limits.clone()), Some(CSVLimits::ROWS_EXCEEDED));
+
+        limits = CSVLimits::new();
+        limits.max_total_bytes = Some(12);
+        assert_eq!(parse(limits.clone()), Some(CSVLimits::TOTAL_BYTES_EXCEEDED));
+
+        limits.max_total_bytes = Some(13);
+        assert_eq!(parse(limits.clone()), None);
+
+        // Kept blank lines are rows too.
+        let mut options = CSVParseOptions::new();
+        options.skip_empty_lines = false;
+        options.limits.max_rows = Some(2);
+        assert_eq!(
+            CSVDocument::parse_string_with_options(&"a\n1\n\n\n\n\n".to_string(), &options).err(),
+            Some(CSVLimits::ROWS_EXCEEDED)
+        );
+    }
+
+    #[test]
+    fn test_parse_string_limits_unterminated_quote() {
+        // An unterminated quote would otherwise swallow the rest of the input into one cell.
+        let mut string: String = "a,b\n1,\"".to_string();
+        string.push_str(&"x,y\n".repeat(1000));
+
+        let mut options = CSVParseOptions::new();
+        options.limits.max_field_size = Some(64);
+
+        let result = CSVDocument::parse_string_with_options(&string, &options);
+        assert_eq!(result.err(), Some(CSVLimits::FIELD_SIZE_EXCEEDED));
+
+        let result = CSVDocument::parse_bytes(string.as_bytes(), &options);
+        assert_eq!(result.err(), Some(CSVLimits::FIELD_SIZE_EXCEEDED));
+    }
+
+    #[test]
+    fn test_parse_bytes_limits_decoded() {
+        // The total size counts the input bytes, not the longer UTF-8 text they decode to.
+        let bytes: &[u8] = b"name\ncaf\xe9\n\xe9\xe9\n";
+        let mut options = CSVParseOptions::new();
+        options.encoding = Some(CSVEncoding::Latin1);
+        options.limits.max_total_bytes = Some(bytes.len());
+
+        let doc = CSVDocument::parse_bytes(bytes, &options).unwrap();
+        assert_eq!(doc.contents[1].cells, vec!["\u{e9}\u{e9}"]);
+
+        options.limits.max_total_bytes = Some(bytes.len() - 1);
+        assert_eq!(
+            CSVDocument::parse_bytes(bytes, &options).err(),
+            Some(CSVLimits::TOTAL_BYTES_EXCEEDED)
+        );
+    }
+}
+
+#[cfg(test)]
+mod round_trip_tests {
+    use super::*;
+    use crate::csv_dialect::CSVDialect;
+    use crate::csv_dialect::CSVLineTerminator;
+    use crate::csv_dialect::CSVTrim;
+    use proptest::prelude::*;
+
+    fn dialect_strategy() -> impl Strategy<Value = CSVDialect> {
+        return (
+            prop::sample::select(vec![',', ';', '\t', '|']),
+            prop::sample::select(vec!['"', '\'']),
+            any::<bool>(),
+            prop::sample::select(vec![
+                CSVLineTerminator::LF,
+                CSVLineTerminator::CRLF,
+                CSVLineTerminator::CR,
+            ]),
+            prop::option::of(Just('#')),
+            // AllFields is left out as it deliberately trims whitespace inside quotes.
+            prop::sample::select(vec![
+                CSVTrim::None,
+                CSVTrim::UnquotedFields,
+                CSVTrim::HeadersOnly,
+            ]),
+            prop::option::of(Just('\\')),
+        )
+            .prop_map(
+                |(delimiter, quote, has_header, line_terminator, comment, trim, escape)| {
+                    let mut dialect = CSVDialect::new();
+                    dialect.delimiter = delimiter;
+                    dialect.quote = quote;
+                    dialect.has_header = has_header;
+                    dialect.line_terminator = line_terminator;
+                    dialect.comment = comment;
+                    dialect.trim = trim;
+                    dialect.escape = escape;
+                    dialect
+                },
+            );
+    }
+
+    // Cells are built from the characters that have a special meaning in at least one dialect.
+    fn cell_strategy() -> impl Strategy<Value = String> {
+        return prop::collection::vec(
+            prop::sample::select(vec![
+                'a', 'b', ' ', ',', ';', '\t', '|', '"', '\'', '\\', '\n', '\r', '#', '\u{e9}',
+                '\u{feff}', 'n',
+            ]),
+            0..6,
+        )
+        .prop_map(|chars| chars.into_iter().collect());
+    }
+
+    fn document_strategy() -> impl Strategy<Value = (CSVDialect, Vec<Vec<String>>)> {
+        return (dialect_strategy(), 1..5usize).prop_flat_map(|(dialect, width)| {
+            (
+                Just(dialect),
+                prop::collection::vec(prop::collection::vec(cell_strategy(), width), 1..6),
+            )
+        });
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip((dialect, records) in document_strategy()) {
+            let mut doc = CSVDocument::new();
+            let mut records = records.into_iter();
+
+            if dialect.has_header {
+                doc.header.cells = records.next().unwrap();
+            }
+
+            for cells in records {
+                let mut row = CSVRow::new();
+                row.cells = cells;
+                doc.add_row(row);
+            }
+
+            let mut write_options = CSVWriteOptions::new();
+            write_options.dialect = dialect.clone();
+            let written = doc.to_string_with_options(&write_options);
+
+            let parsed = CSVDocument::parse_string_with_options(
+                &written,
+                &CSVParseOptions::from_dialect(dialect),
+            );
+            prop_assert!(parsed.is_ok(), "{:?} failed with {:?}", written, parsed.err());
+
+            let parsed = parsed.unwrap();
+            let contents: Vec<&Vec<String>> = parsed.contents.iter().map(|r| &r.cells).collect();
+            let expected: Vec<&Vec<String>> = doc.contents.iter().map(|r| &r.cells).collect();
+
+            prop_assert_eq!(&parsed.header.cells, &doc.header.cells, "{:?}", written);
+            prop_assert_eq!(contents, expected, "{:?}", written);
+        }
+    }
 }
@@ -0,0 +1,330 @@
+// The text encodings that documents can be read from and written to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVEncoding {
+    Utf8,
+    Utf16LE,
+    Utf16BE,
+    Latin1,
+    Windows1252,
+}
+
+// Windows-1252 differs from Latin-1 only in the 0x80 - 0x9F range, where it maps printable characters
+// instead of the C1 control codes. Zero marks the five bytes that Windows-1252 leaves undefined.
+const WINDOWS_1252_HIGH: [u16; 32] = [
+    0x20AC, 0, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0, 0x017D, 0, 0, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x02DC,
+    0x2122, 0x0161, 0x203A, 0x0153, 0, 0x017E, 0x0178,
+];
+
+impl CSVEncoding {
+    // Guesses the encoding of the input. A byte order mark always wins, otherwise valid UTF-8 is
+    // assumed to be UTF-8, text with a regular pattern of zero bytes to be BOM-less UTF-16 and
+    // anything else to be one of the single byte encodings.
+    pub fn detect(input: &[u8]) -> CSVEncoding {
+        if input.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            return CSVEncoding::Utf8;
+        } else if input.starts_with(&[0xFF, 0xFE]) {
+            return CSVEncoding::Utf16LE;
+        } else if input.starts_with(&[0xFE, 0xFF]) {
+            return CSVEncoding::Utf16BE;
+        }
+
+        // NUL bytes are valid UTF-8, so look for the UTF-16 pattern first.
+        if input.len() & 1 == 0 {
+            let pairs = input.len() / 2;
+            let even_zeros = input.iter().step_by(2).filter(|b| **b == 0).count();
+            let odd_zeros = input.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+
+            // Mostly ASCII text encoded as UTF-16 has a zero in every other byte.
+            if odd_zeros * 2 > pairs && even_zeros * 10 < pairs {
+                return CSVEncoding::Utf16LE;
+            } else if even_zeros * 2 > pairs && odd_zeros * 10 < pairs {
+                return CSVEncoding::Utf16BE;
+            }
+        }
+
+        if std::str::from_utf8(input).is_ok() {
+            return CSVEncoding::Utf8;
+        }
+
+        for b in input {
+            if (0x80..=0x9F).contains(b) && WINDOWS_1252_HIGH[(*b - 0x80) as usize] == 0 {
+                return CSVEncoding::Latin1;
+            }
+        }
+
+        return CSVEncoding::Windows1252;
+    }
+
+    // Decodes the input into a string. A byte order mark is kept as a leading U+FEFF so the parser
+    // can record that it was present.
+    pub fn decode(&self, input: &[u8]) -> Result<String, &'static str> {
+        match self {
+            CSVEncoding::Utf8 => match std::str::from_utf8(input) {
+                Ok(s) => return Ok(s.to_string()),
+                Err(_) => return Err("The input is not valid UTF-8."),
+            },
+            CSVEncoding::Utf16LE | CSVEncoding::Utf16BE => {
+                if input.len() & 1 != 0 {
+                    return Err("The input is not valid UTF-16, it has an odd number of bytes.");
+                }
+
+                let units = input.chunks(2).map(|pair| {
+                    if *self == CSVEncoding::Utf16LE {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    }
+                });
+
+                let mut str = String::with_capacity(input.len() / 2);
+
+                for c in std::char::decode_utf16(units) {
+                    match c {
+                        Ok(c) => str.push(c),
+                        Err(_) => {
+                            return Err(
+                                "The input is not valid UTF-16, it contains an unpaired surrogate.",
+                            )
+                        }
+                    }
+                }
+
+                return Ok(str);
+            }
+            CSVEncoding::Latin1 => {
+                return Ok(input.iter().map(|b| *b as char).collect());
+            }
+            CSVEncoding::Windows1252 => {
+                let mut str = String::with_capacity(input.len());
+
+                for b in input {
+                    if (0x80..=0x9F).contains(b) {
+                        let code = WINDOWS_1252_HIGH[(*b - 0x80) as usize];
+
+                        if code == 0 {
+                            return Err(
+                                "The input contains a byte that is undefined in Windows-1252.",
+                            );
+                        }
+
+                        // Every entry in the table is a valid scalar value.
+                        str.push(std::char::from_u32(code as u32).unwrap());
+                    } else {
+                        str.push(*b as char);
+                    }
+                }
+
+                return Ok(str);
+            }
+        }
+    }
+
+    // Only the Unicode encodings have a byte order mark, Latin-1 and Windows-1252 cannot encode one.
+    pub(crate) fn has_bom(&self) -> bool {
+        return match self {
+            CSVEncoding::Utf8 | CSVEncoding::Utf16LE | CSVEncoding::Utf16BE => true,
+            CSVEncoding::Latin1 | CSVEncoding::Windows1252 => false,
+        };
+    }
+
+    // Returns how many bytes at the start of a chunk of a stream can be decoded on their own, holding
+    // back a character that is split across the end of the chunk. Invalid input is not held back so
+    // that decode reports it.
+    pub(crate) fn complete_prefix(&self, input: &[u8]) -> usize {
+        match self {
+            CSVEncoding::Utf8 => match std::str::from_utf8(input) {
+                Err(e) if e.error_len().is_none() => return e.valid_up_to(),
+                _ => return input.len(),
+            },
+            CSVEncoding::Utf16LE | CSVEncoding::Utf16BE => {
+                let len = input.len() & !1;
+
+                if len < 2 {
+                    return len;
+                }
+
+                let last = if *self == CSVEncoding::Utf16LE {
+                    u16::from_le_bytes([input[len - 2], input[len - 1]])
+                } else {
+                    u16::from_be_bytes([input[len - 2], input[len - 1]])
+                };
+
+                // A high surrogate needs the unit that follows it.
+                if (0xD800..=0xDBFF).contains(&last) {
+                    return len - 2;
+                }
+
+                return len;
+            }
+            CSVEncoding::Latin1 | CSVEncoding::Windows1252 => return input.len(),
+        }
+    }
+
+    // Encodes the text, a leading U+FEFF is written as the byte order mark of the encoding.
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, &'static str> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+
+        match self {
+            CSVEncoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+            CSVEncoding::Utf16LE => {
+                for unit in text.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            CSVEncoding::Utf16BE => {
+                for unit in text.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            CSVEncoding::Latin1 => {
+                for c in text.chars() {
+                    if (c as u32) > 0xFF {
+                        return Err(
+                            "The text contains a character that cannot be represented in Latin-1.",
+                        );
+                    }
+
+                    bytes.push(c as u8);
+                }
+            }
+            CSVEncoding::Windows1252 => {
+                for c in text.chars() {
+                    let code = c as u32;
+
+                    if code < 0x80 || (0xA0..=0xFF).contains(&code) {
+                        bytes.push(code as u8);
+                    } else {
+                        match WINDOWS_1252_HIGH.iter().position(|v| *v != 0 && *v as u32 == code) {
+                            Some(index) => bytes.push(0x80 + index as u8),
+                            None => {
+                                return Err("The text contains a character that cannot be represented in Windows-1252.")
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_bom() {
+        assert_eq!(
+            CSVEncoding::detect(&[0xEF, 0xBB, 0xBF, b'a']),
+            CSVEncoding::Utf8
+        );
+        assert_eq!(
+            CSVEncoding::detect(&[0xFF, 0xFE, b'a', 0]),
+            CSVEncoding::Utf16LE
+        );
+        assert_eq!(
+            CSVEncoding::detect(&[0xFE, 0xFF, 0, b'a']),
+            CSVEncoding::Utf16BE
+        );
+    }
+
+    #[test]
+    fn test_detect_without_bom() {
+        assert_eq!(
+            CSVEncoding::detect("caf\u{e9},1".as_bytes()),
+            CSVEncoding::Utf8
+        );
+        assert_eq!(CSVEncoding::detect(b"a\0,\0b\0"), CSVEncoding::Utf16LE);
+        assert_eq!(CSVEncoding::detect(b"\0a\0,\0b"), CSVEncoding::Utf16BE);
+        assert_eq!(
+            CSVEncoding::detect(b"caf\xe9,\x80"),
+            CSVEncoding::Windows1252
+        );
+        assert_eq!(CSVEncoding::detect(b"caf\xe9,\x81"), CSVEncoding::Latin1);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(CSVEncoding::Latin1.decode(b"caf\xe9").unwrap(), "caf\u{e9}");
+        assert_eq!(
+            CSVEncoding::Windows1252.decode(b"\x80 caf\xe9").unwrap(),
+            "\u{20ac} caf\u{e9}"
+        );
+        assert_eq!(
+            CSVEncoding::Utf16LE
+                .decode(&[0xFF, 0xFE, b'h', 0, b'i', 0])
+                .unwrap(),
+            "\u{feff}hi"
+        );
+        assert_eq!(
+            CSVEncoding::Utf16BE
+                .decode(&[0xD8, 0x3D, 0xDE, 0x00])
+                .unwrap(),
+            "\u{1f600}"
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(
+            CSVEncoding::Utf8.decode(b"caf\xe9"),
+            Err("The input is not valid UTF-8.")
+        );
+        assert_eq!(
+            CSVEncoding::Utf16LE.decode(&[b'a', 0, b'b']),
+            Err("The input is not valid UTF-16, it has an odd number of bytes.")
+        );
+        assert_eq!(
+            CSVEncoding::Windows1252.decode(b"\x8d"),
+            Err("The input contains a byte that is undefined in Windows-1252.")
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let text = "\u{feff}name,price\ncaf\u{e9},\u{20ac}5\n";
+
+        for encoding in &[
+            CSVEncoding::Utf8,
+            CSVEncoding::Utf16LE,
+            CSVEncoding::Utf16BE,
+        ] {
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(CSVEncoding::detect(&bytes), *encoding);
+            assert_eq!(encoding.decode(&bytes).unwrap(), text);
+        }
+
+        let bytes = CSVEncoding::Windows1252.encode(&text[3..]).unwrap();
+        assert_eq!(bytes, b"name,price\ncaf\xe9,\x805\n");
+    }
+
+    #[test]
+    fn test_complete_prefix() {
+        assert_eq!(CSVEncoding::Utf8.complete_prefix("a\u{e9}".as_bytes()), 3);
+        assert_eq!(
+            CSVEncoding::Utf8.complete_prefix(&"a\u{e9}".as_bytes()[..2]),
+            1
+        );
+        assert_eq!(CSVEncoding::Utf8.complete_prefix(b"a\xff"), 2);
+        assert_eq!(CSVEncoding::Utf16LE.complete_prefix(&[b'a', 0, b'b']), 2);
+        assert_eq!(
+            CSVEncoding::Utf16BE.complete_prefix(&[0, b'a', 0xD8, 0x3D, 0xDE]),
+            2
+        );
+        assert_eq!(CSVEncoding::Latin1.complete_prefix(b"caf\xe9"), 4);
+    }
+
+    #[test]
+    fn test_encode_unrepresentable() {
+        assert_eq!(
+            CSVEncoding::Latin1.encode("\u{20ac}"),
+            Err("The text contains a character that cannot be represented in Latin-1.")
+        );
+        assert_eq!(
+            CSVEncoding::Windows1252.encode("\u{feff}"),
+            Err("The text contains a character that cannot be represented in Windows-1252.")
+        );
+    }
+}
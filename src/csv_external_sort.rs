@@ -0,0 +1,495 @@
+use crate::csv_encoding::CSVEncoding;
+use crate::csv_options::CSVParseOptions;
+use crate::csv_options::CSVWriteOptions;
+use crate::csv_reader::CSVReader;
+use crate::csv_row::CSVRow;
+use crate::csv_row::LineWriter;
+use crate::csv_sort::compare_rows;
+use crate::csv_sort::resolve_keys;
+use crate::csv_sort::CSVSortKey;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering as AtomicOrdering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// Gives every run file created by this process a different name.
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Sorts CSV that does not fit in memory. Rows are read from a stream, sorted in runs that fit in
+// max_run_bytes, spilled to temporary files and merged into the output. The order is the same as
+// CSVDocument::sort_by_columns with the same keys, including its stability.
+#[derive(Clone)]
+pub struct CSVExternalSort {
+    pub keys: Vec<CSVSortKey>,
+    pub temp_dir: PathBuf, // Where the sorted runs are written. Defaults to the system temp directory.
+    pub max_run_bytes: usize, // The rough amount of memory the rows of one run may use.
+    pub max_fan_in: usize, // The most runs merged at once, which bounds the amount of open files.
+    pub parse_options: CSVParseOptions,
+    pub write_options: CSVWriteOptions,
+}
+
+impl CSVExternalSort {
+    pub fn new(keys: &[CSVSortKey]) -> CSVExternalSort {
+        return CSVExternalSort {
+            keys: keys.to_vec(),
+            temp_dir: std::env::temp_dir(),
+            max_run_bytes: 64 * 1024 * 1024,
+            max_fan_in: 64,
+            parse_options: CSVParseOptions::new(),
+            write_options: CSVWriteOptions::new(),
+        };
+    }
+
+    // Sorts the input into the output. Temporary files are removed before returning, whether the
+    // sort succeeded or not.
+    pub fn sort<R: Read, W: Write>(&self, input: R, output: W) -> Result<(), &'static str> {
+        let mut reader = match CSVReader::new(input, &self.parse_options) {
+            Ok(reader) => reader,
+            Err(e) => return Err(e),
+        };
+
+        let header = reader.header().clone();
+
+        let keys = match resolve_keys(&header, &self.keys) {
+            Ok(keys) => keys,
+            Err(e) => return Err(e),
+        };
+
+        let mut runs = Runs { paths: vec![] };
+        let mut level: Vec<PathBuf> = vec![];
+        let mut rows: Vec<CSVRow> = vec![];
+        let mut run_bytes = 0;
+        let mut width: Option<usize> = None;
+
+        loop {
+            let row = match reader.next_row() {
+                Ok(row) => row,
+                Err(e) => return Err(e),
+            };
+
+            if let Some(row) = &row {
+                // Every row needs the key columns, so rows have to be as wide as the header.
+                let expected = if header.is_empty() {
+                    *width.get_or_insert(row.len())
+                } else {
+                    header.len()
+                };
+
+                if row.len() != expected {
+                    return Err("The amount of cells in the row was invalid.");
+                }
+
+                run_bytes += std::mem::size_of::<CSVRow>()
+                    + row
+                        .cells
+                        .iter()
+                        .map(|c| c.len() + std::mem::size_of::<String>())
+                        .sum::<usize>();
+            }
+
+            let finished = row.is_none();
+
+            if let Some(row) = row {
+                rows.push(row);
+            }
+
+            if (finished || run_bytes >= self.max_run_bytes) && !rows.is_empty() {
+                rows.sort_by(|a, b| compare_rows(a, b, &keys));
+
+                // Input that fits in one run is written straight out.
+                if finished && level.is_empty() {
+                    return self.write(&header, rows.into_iter().map(Ok), output);
+                }
+
+                match runs.spill(&self.temp_dir, rows.drain(..).map(Ok)) {
+                    Ok(path) => level.push(path),
+                    Err(e) => return Err(e),
+                }
+
+                run_bytes = 0;
+            }
+
+            if finished {
+                break;
+            }
+        }
+
+        // Too many runs are merged in groups into longer runs first. The groups hold consecutive
+        // runs and replace them in place, which keeps the merge stable.
+        let fan_in = self.max_fan_in.max(2);
+
+        while level.len() > fan_in {
+            let mut next = vec![];
+
+            for group in level.chunks(fan_in) {
+                let merged = match Merge::new(group, &keys) {
+                    Ok(merged) => merged,
+                    Err(e) => return Err(e),
+                };
+
+                match runs.spill(&self.temp_dir, merged) {
+                    Ok(path) => next.push(path),
+                    Err(e) => return Err(e),
+                }
+
+                for path in group {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+
+            level = next;
+        }
+
+        return match Merge::new(&level, &keys) {
+            Ok(merged) => self.write(&header, merged, output),
+            Err(e) => Err(e),
+        };
+    }
+
+    fn write<I, W>(&self, header: &CSVRow, rows: I, output: W) -> Result<(), &'static str>
+    where
+        I: Iterator<Item = Result<CSVRow, &'static str>>,
+        W: Write,
+    {
+        let options = &self.write_options;
+        let writer = LineWriter::new(header, options);
+        let mut output = BufWriter::new(output);
+
+        let mut line = writer.bom();
+        line += &writer.header(header);
+
+        for row in rows {
+            match row {
+                Ok(row) => line += &writer.row(&row),
+                Err(e) => return Err(e),
+            }
+
+            if let Err(e) = write_text(&mut output, options, &line) {
+                return Err(e);
+            }
+
+            line.clear();
+        }
+
+        if let Err(e) = write_text(&mut output, options, &line) {
+            return Err(e);
+        }
+
+        return match output.flush() {
+            Ok(_) => Ok(()),
+            Err(_) => Err("The output could not be written."),
+        };
+    }
+}
+
+fn write_text<W: Write>(
+    output: &mut W,
+    options: &CSVWriteOptions,
+    text: &str,
+) -> Result<(), &'static str> {
+    let bytes = match options.encoding.encode(text) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(e),
+    };
+
+    return match output.write_all(&bytes) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("The output could not be written."),
+    };
+}
+
+// Every run file created by a sort, which are deleted when this is dropped.
+struct Runs {
+    paths: Vec<PathBuf>,
+}
+
+impl Runs {
+    // Runs are written as plain CSV in the default dialect, which keeps every cell and quoted flag.
+    fn spill<I>(&mut self, dir: &Path, rows: I) -> Result<PathBuf, &'static str>
+    where
+        I: Iterator<Item = Result<CSVRow, &'static str>>,
+    {
+        // The file is created exclusively, so an existing file or link is never written through.
+        // The time in the name keeps it apart from files left by other processes with the same id.
+        let mut attempts = 0;
+
+        let (path, file) = loop {
+            let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(time) => time.subsec_nanos(),
+                Err(_) => 0,
+            };
+            let name = format!(
+                "csv_sort_{}_{}_{}.csv",
+                std::process::id(),
+                nanos,
+                RUN_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+            );
+            let path = dir.join(name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists && attempts < 16 => attempts += 1,
+                Err(_) => return Err("A temporary file could not be created."),
+            }
+        };
+
+        self.paths.push(path.clone());
+        let mut file = BufWriter::new(file);
+
+        for row in rows {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => return Err(e),
+            };
+
+            let written = file
+                .write_all(row.to_string().as_bytes())
+                .and_then(|_| file.write_all(b"\n"));
+
+            if written.is_err() {
+                return Err("A temporary file could not be written.");
+            }
+        }
+
+        return match file.flush() {
+            Ok(_) => Ok(path),
+            Err(_) => Err("A temporary file could not be written."),
+        };
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// The next row of a run. The heap is a max heap, so the ordering is reversed to pop the smallest row
+// first, and ties go to the earlier run to keep the sort stable.
+struct Head<'a> {
+    row: CSVRow,
+    run: usize,
+    keys: &'a [(usize, &'a CSVSortKey)],
+}
+
+impl<'a> Ord for Head<'a> {
+    fn cmp(&self, other: &Head<'a>) -> Ordering {
+        return compare_rows(&self.row, &other.row, self.keys)
+            .then(self.run.cmp(&other.run))
+            .reverse();
+    }
+}
+
+impl<'a> PartialOrd for Head<'a> {
+    fn partial_cmp(&self, other: &Head<'a>) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl<'a> PartialEq for Head<'a> {
+    fn eq(&self, other: &Head<'a>) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl<'a> Eq for Head<'a> {}
+
+// Merges sorted runs into one sorted sequence of rows.
+struct Merge<'a> {
+    readers: Vec<CSVReader<BufReader<File>>>,
+    heap: BinaryHeap<Head<'a>>,
+    keys: &'a [(usize, &'a CSVSortKey)],
+}
+
+impl<'a> Merge<'a> {
+    fn new(
+        paths: &[PathBuf],
+        keys: &'a [(usize, &'a CSVSortKey)],
+    ) -> Result<Merge<'a>, &'static str> {
+        let mut merge = Merge {
+            readers: vec![],
+            heap: BinaryHeap::new(),
+            keys,
+        };
+
+        for path in paths {
+            match open_run(path) {
+                Ok(reader) => merge.readers.push(reader),
+                Err(e) => return Err(e),
+            }
+        }
+
+        for run in 0..merge.readers.len() {
+            if let Err(e) = merge.advance(run) {
+                return Err(e);
+            }
+        }
+
+        return Ok(merge);
+    }
+
+    fn advance(&mut self, run: usize) -> Result<(), &'static str> {
+        return match self.readers[run].next_row() {
+            Ok(Some(row)) => {
+                self.heap.push(Head {
+                    row,
+                    run,
+                    keys: self.keys,
+                });
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+    }
+}
+
+impl<'a> Iterator for Merge<'a> {
+    type Item = Result<CSVRow, &'static str>;
+
+    fn next(&mut self) -> Option<Result<CSVRow, &'static str>> {
+        let head = self.heap.pop()?;
+
+        if let Err(e) = self.advance(head.run) {
+            return Some(Err(e));
+        }
+
+        return Some(Ok(head.row));
+    }
+}
+
+fn open_run(path: &PathBuf) -> Result<CSVReader<BufReader<File>>, &'static str> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Err("A temporary file could not be read."),
+    };
+
+    let mut options = CSVParseOptions::new();
+    options.dialect.has_header = false;
+    options.encoding = Some(CSVEncoding::Utf8);
+
+    return CSVReader::new(BufReader::new(file), &options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_document::CSVDocument;
+    use crate::csv_sort::CSVSortDirection;
+    use crate::csv_sort::CSVSortMode;
+
+    fn input() -> String {
+        let mut input = String::from("id,group,note\n");
+
+        for i in 0..500 {
+            let note = match i % 4 {
+                0 => "\"multi\nline\"",
+                1 => "\"\"",
+                2 => "",
+                _ => "\"a, b\"",
+            };
+
+            // Numeric keys that cannot be ordered as numbers sort as text after the others.
+            let group = match i % 50 {
+                0 => "NaN".to_string(),
+                25 => "inf".to_string(),
+                _ => ((i * 7919) % 13).to_string(),
+            };
+
+            input += &format!("{},{},{}\n", i, group, note);
+        }
+
+        return input;
+    }
+
+    fn keys() -> Vec<CSVSortKey> {
+        return vec![
+            CSVSortKey::new("group", CSVSortDirection::Descending, CSVSortMode::Numeric),
+            CSVSortKey::new(
+                "note",
+                CSVSortDirection::Ascending,
+                CSVSortMode::Lexicographic,
+            ),
+        ];
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("csv_parser_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn test_external_sort_matches_in_memory() {
+        let input = input();
+        let mut doc = CSVDocument::parse_string(&input).unwrap();
+        doc.sort_by_columns(&keys()).unwrap();
+
+        let dir = temp_dir("runs");
+
+        // Runs of 2000 bytes split the input into a few dozen runs, which takes three passes to
+        // merge four at a time and one pass otherwise.
+        for (max_run_bytes, max_fan_in) in &[(2000, 64), (2000, 4), (2000, 2), (usize::MAX, 64)] {
+            let mut sorter = CSVExternalSort::new(&keys());
+            sorter.temp_dir = dir.clone();
+            sorter.max_run_bytes = *max_run_bytes;
+            sorter.max_fan_in = *max_fan_in;
+
+            let mut output: Vec<u8> = vec![];
+            sorter.sort(input.as_bytes(), &mut output).unwrap();
+
+            assert_eq!(String::from_utf8(output).unwrap(), doc.to_string());
+        }
+
+        // Every run file has been removed.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_external_sort_errors() {
+        let dir = temp_dir("errors");
+        let mut sorter = CSVExternalSort::new(&keys());
+        sorter.temp_dir = dir.clone();
+        sorter.max_run_bytes = 1;
+
+        let mut output: Vec<u8> = vec![];
+        assert_eq!(
+            sorter.sort(&b"id,group,note\n1,2,3\n4,5,6\n7,8\n"[..], &mut output),
+            Err("The amount of cells in the row was invalid.")
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        sorter.keys = vec![CSVSortKey::new(
+            "missing",
+            CSVSortDirection::Ascending,
+            CSVSortMode::Lexicographic,
+        )];
+        assert_eq!(
+            sorter.sort(&b"id\n1\n"[..], &mut output),
+            Err("The column does not exist.")
+        );
+
+        sorter.temp_dir = dir.join("missing");
+        sorter.keys = keys();
+        assert_eq!(
+            sorter.sort(input().as_bytes(), &mut output),
+            Err("A temporary file could not be created.")
+        );
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
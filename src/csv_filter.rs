@@ -0,0 +1,337 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::cmp::Ordering;
+
+// The comparisons a predicate can make between a cell and a value. Cells and values that both parse
+// as finite numbers are compared as numbers, anything else, including NaN, is compared as text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVOperator {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+impl CSVOperator {
+    // Parses an operator typed by a user, such as ">=" or "contains".
+    pub fn parse(s: &str) -> Option<CSVOperator> {
+        return match s.trim().to_lowercase().as_str() {
+            "=" | "==" | "eq" => Some(CSVOperator::Eq),
+            "!=" | "<>" | "ne" => Some(CSVOperator::Ne),
+            ">" | "gt" => Some(CSVOperator::Gt),
+            ">=" | "ge" => Some(CSVOperator::Ge),
+            "<" | "lt" => Some(CSVOperator::Lt),
+            "<=" | "le" => Some(CSVOperator::Le),
+            "contains" => Some(CSVOperator::Contains),
+            "starts_with" => Some(CSVOperator::StartsWith),
+            "ends_with" => Some(CSVOperator::EndsWith),
+            _ => None,
+        };
+    }
+
+    fn test(&self, cell: &str, value: &str) -> bool {
+        let number = |s: &str| s.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+
+        let ordering = match (number(cell), number(value)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => Some(cell.cmp(value)),
+        };
+
+        return match self {
+            CSVOperator::Eq => ordering == Some(Ordering::Equal),
+            CSVOperator::Ne => ordering != Some(Ordering::Equal),
+            CSVOperator::Gt => ordering == Some(Ordering::Greater),
+            CSVOperator::Ge => {
+                ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal)
+            }
+            CSVOperator::Lt => ordering == Some(Ordering::Less),
+            CSVOperator::Le => {
+                ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal)
+            }
+            CSVOperator::Contains => cell.contains(value),
+            CSVOperator::StartsWith => cell.starts_with(value),
+            CSVOperator::EndsWith => cell.ends_with(value),
+        };
+    }
+}
+
+// A condition on the cells of a row, built with col or directly from its variants when the condition
+// comes from user input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CSVPredicate {
+    Compare(String, CSVOperator, String), // The column name, the operator and the value.
+    IsEmpty(String),
+    And(Box<CSVPredicate>, Box<CSVPredicate>),
+    Or(Box<CSVPredicate>, Box<CSVPredicate>),
+    Not(Box<CSVPredicate>),
+}
+
+impl CSVPredicate {
+    pub fn and(self, other: CSVPredicate) -> CSVPredicate {
+        return CSVPredicate::And(Box::new(self), Box::new(other));
+    }
+
+    pub fn or(self, other: CSVPredicate) -> CSVPredicate {
+        return CSVPredicate::Or(Box::new(self), Box::new(other));
+    }
+
+    // Tests a row against the predicate, looking columns up in the header. A row too short to have
+    // the column is treated as having an empty cell there.
+    pub fn matches(&self, header: &CSVRow, row: &CSVRow) -> Result<bool, &'static str> {
+        let cell = |name: &String| match header.cells.iter().position(|cell| cell == name) {
+            Some(index) => Ok(row.cells.get(index).map(|c| c.as_str()).unwrap_or("")),
+            None => Err("The column does not exist."),
+        };
+
+        return match self {
+            CSVPredicate::Compare(name, operator, value) => match cell(name) {
+                Ok(cell) => Ok(operator.test(cell, value)),
+                Err(e) => Err(e),
+            },
+            CSVPredicate::IsEmpty(name) => match cell(name) {
+                Ok(cell) => Ok(cell.is_empty()),
+                Err(e) => Err(e),
+            },
+            CSVPredicate::And(a, b) => match a.matches(header, row) {
+                Ok(true) => b.matches(header, row),
+                other => other,
+            },
+            CSVPredicate::Or(a, b) => match a.matches(header, row) {
+                Ok(false) => b.matches(header, row),
+                other => other,
+            },
+            CSVPredicate::Not(a) => match a.matches(header, row) {
+                Ok(result) => Ok(!result),
+                Err(e) => Err(e),
+            },
+        };
+    }
+
+    // Checks that every column the predicate names is in the header, even ones matches would skip.
+    fn check_columns(&self, header: &CSVRow) -> Result<(), &'static str> {
+        return match self {
+            CSVPredicate::Compare(name, _, _) | CSVPredicate::IsEmpty(name) => {
+                if header.cells.contains(name) {
+                    Ok(())
+                } else {
+                    Err("The column does not exist.")
+                }
+            }
+            CSVPredicate::And(a, b) | CSVPredicate::Or(a, b) => {
+                a.check_columns(header).and(b.check_columns(header))
+            }
+            CSVPredicate::Not(a) => a.check_columns(header),
+        };
+    }
+}
+
+// Negates a predicate, e.g. !col("country").eq("NZ").
+impl std::ops::Not for CSVPredicate {
+    type Output = CSVPredicate;
+
+    fn not(self) -> CSVPredicate {
+        return CSVPredicate::Not(Box::new(self));
+    }
+}
+
+// The starting point of a predicate, e.g. col("age").gt(30).
+pub struct CSVColumn {
+    name: String,
+}
+
+pub fn col(name: &str) -> CSVColumn {
+    return CSVColumn {
+        name: name.to_string(),
+    };
+}
+
+impl CSVColumn {
+    pub fn compare<V: ToString>(self, operator: CSVOperator, value: V) -> CSVPredicate {
+        return CSVPredicate::Compare(self.name, operator, value.to_string());
+    }
+
+    pub fn eq<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Eq, value);
+    }
+
+    pub fn ne<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Ne, value);
+    }
+
+    pub fn gt<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Gt, value);
+    }
+
+    pub fn ge<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Ge, value);
+    }
+
+    pub fn lt<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Lt, value);
+    }
+
+    pub fn le<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Le, value);
+    }
+
+    pub fn contains<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::Contains, value);
+    }
+
+    pub fn starts_with<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::StartsWith, value);
+    }
+
+    pub fn ends_with<V: ToString>(self, value: V) -> CSVPredicate {
+        return self.compare(CSVOperator::EndsWith, value);
+    }
+
+    pub fn is_empty(self) -> CSVPredicate {
+        return CSVPredicate::IsEmpty(self.name);
+    }
+}
+
+impl CSVDocument {
+    // Returns a new document with the same header holding the rows the closure returns true for.
+    // Comments are not copied since their positions would no longer line up.
+    pub fn filter<F: FnMut(&CSVRow) -> bool>(&self, mut f: F) -> CSVDocument {
+        return CSVDocument {
+            header: self.header.clone(),
+            contents: self.contents.iter().filter(|row| f(row)).cloned().collect(),
+            has_bom: self.has_bom,
+            encoding: self.encoding,
+            width_policy: self.width_policy,
+            ..CSVDocument::new()
+        };
+    }
+
+    // Returns a new document holding the rows that match the predicate.
+    pub fn filter_by(&self, predicate: &CSVPredicate) -> Result<CSVDocument, &'static str> {
+        if let Err(e) = predicate.check_columns(&self.header) {
+            return Err(e);
+        }
+
+        let mut error: Option<&'static str> = None;
+
+        let doc = self.filter(|row| match predicate.matches(&self.header, row) {
+            Ok(result) => result,
+            Err(e) => {
+                error = Some(e);
+                false
+            }
+        });
+
+        return match error {
+            Some(e) => Err(e),
+            None => Ok(doc),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> CSVDocument {
+        let string: String =
+            "name,age,country\nann,41,NZ\nbob,29,NZ\ncat,35,AU\ndan,100,NZ\neve,,NZ\n".to_string();
+
+        return CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    fn names(doc: &CSVDocument) -> Vec<&str> {
+        return doc
+            .contents
+            .iter()
+            .map(|row| row.cells[0].as_str())
+            .collect();
+    }
+
+    #[test]
+    fn test_filter() {
+        let doc = document();
+        let filtered = doc.filter(|row| row.cells[0].as_str() < "c");
+
+        assert_eq!(filtered.header, doc.header);
+        assert_eq!(names(&filtered), vec!["ann", "bob"]);
+        assert_eq!(doc.row_count(), 5);
+    }
+
+    #[test]
+    fn test_filter_by() {
+        let doc = document();
+
+        let predicate = col("age").gt(30).and(col("country").eq("NZ"));
+        assert_eq!(
+            names(&doc.filter_by(&predicate).unwrap()),
+            vec!["ann", "dan"]
+        );
+
+        let predicate = col("age").is_empty().or(col("name").starts_with("c"));
+        assert_eq!(
+            names(&doc.filter_by(&predicate).unwrap()),
+            vec!["cat", "eve"]
+        );
+
+        let predicate = !col("country").eq("NZ");
+        assert_eq!(names(&doc.filter_by(&predicate).unwrap()), vec!["cat"]);
+
+        assert_eq!(
+            doc.filter_by(&col("height").gt(1)).err(),
+            Some("The column does not exist.")
+        );
+
+        // Columns are checked even when no row is tested against them.
+        let predicate = col("age").gt(200).and(col("height").gt(1));
+        assert_eq!(
+            doc.filter_by(&predicate).err(),
+            Some("The column does not exist.")
+        );
+        assert_eq!(
+            doc.filter(|_| false).filter_by(&col("height").gt(1)).err(),
+            Some("The column does not exist.")
+        );
+    }
+
+    #[test]
+    fn test_filter_by_not_a_number() {
+        let string: String = "x\nNaN\ninf\nInfinity\n1\n".to_string();
+        let doc = CSVDocument::parse_string(&string).unwrap();
+
+        assert_eq!(
+            names(&doc.filter_by(&col("x").eq("NaN")).unwrap()),
+            vec!["NaN"]
+        );
+        assert_eq!(
+            names(&doc.filter_by(&col("x").ne("NaN")).unwrap()),
+            vec!["inf", "Infinity", "1"]
+        );
+        assert_eq!(
+            names(&doc.filter_by(&col("x").eq("inf")).unwrap()),
+            vec!["inf"]
+        );
+    }
+
+    #[test]
+    fn test_predicate_from_input() {
+        let doc = document();
+        let input = "age >= 35";
+        let parts: Vec<&str> = input.split_whitespace().collect();
+
+        let predicate = col(parts[0]).compare(CSVOperator::parse(parts[1]).unwrap(), parts[2]);
+        assert_eq!(
+            names(&doc.filter_by(&predicate).unwrap()),
+            vec!["ann", "cat", "dan"]
+        );
+
+        assert_eq!(CSVOperator::parse("<>"), Some(CSVOperator::Ne));
+        assert_eq!(CSVOperator::parse("like"), None);
+    }
+}
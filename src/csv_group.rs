@@ -0,0 +1,304 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// The ways a column of a group can be summarised. Empty cells are skipped by everything except Count,
+// which counts the rows of the group.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CSVAggregation {
+    Count,
+    Sum,
+    Mean,
+    Min, // Compares numbers when every value is a number, otherwise text.
+    Max,
+    DistinctCount,
+    First,
+    Last,
+    Concat(String), // Joins the values with the separator.
+}
+
+impl CSVAggregation {
+    fn name(&self) -> &'static str {
+        return match self {
+            CSVAggregation::Count => "count",
+            CSVAggregation::Sum => "sum",
+            CSVAggregation::Mean => "mean",
+            CSVAggregation::Min => "min",
+            CSVAggregation::Max => "max",
+            CSVAggregation::DistinctCount => "distinct_count",
+            CSVAggregation::First => "first",
+            CSVAggregation::Last => "last",
+            CSVAggregation::Concat(_) => "concat",
+        };
+    }
+
+    // Summarises the cells of one column of a group.
+    pub(crate) fn apply(&self, cells: &[&String]) -> Result<String, &'static str> {
+        let values: Vec<&String> = cells.iter().filter(|c| !c.is_empty()).cloned().collect();
+
+        let numbers = || -> Result<Vec<f64>, &'static str> {
+            let mut numbers = vec![];
+
+            for value in values.iter() {
+                // NaN and infinities are not numbers here, as in CSVColumnStats.
+                match value.trim().parse::<f64>() {
+                    Ok(number) if number.is_finite() => numbers.push(number),
+                    _ => return Err("The column contains a value that is not a number."),
+                }
+            }
+
+            return Ok(numbers);
+        };
+
+        return match self {
+            CSVAggregation::Count => Ok(cells.len().to_string()),
+            CSVAggregation::Sum => match numbers() {
+                Ok(numbers) => Ok(sum(&numbers).to_string()),
+                Err(e) => Err(e),
+            },
+            CSVAggregation::Mean => match numbers() {
+                Ok(numbers) if numbers.is_empty() => Ok(String::new()),
+                Ok(numbers) => Ok((sum(&numbers) / numbers.len() as f64).to_string()),
+                Err(e) => Err(e),
+            },
+            CSVAggregation::Min | CSVAggregation::Max => {
+                let is_max = *self == CSVAggregation::Max;
+
+                let best = match numbers() {
+                    Ok(numbers) => {
+                        let mut best: Option<(f64, &String)> = None;
+
+                        for (number, value) in numbers.into_iter().zip(values.iter()) {
+                            let better = match best {
+                                Some((b, _)) if is_max => number > b,
+                                Some((b, _)) => number < b,
+                                None => true,
+                            };
+
+                            if better {
+                                best = Some((number, value));
+                            }
+                        }
+
+                        best.map(|(_, value)| value)
+                    }
+                    Err(_) if is_max => values.iter().max().cloned(),
+                    Err(_) => values.iter().min().cloned(),
+                };
+
+                Ok(best.cloned().unwrap_or_default())
+            }
+            CSVAggregation::DistinctCount => {
+                Ok(values.iter().collect::<HashSet<_>>().len().to_string())
+            }
+            CSVAggregation::First => Ok(values.first().map(|v| v.to_string()).unwrap_or_default()),
+            CSVAggregation::Last => Ok(values.last().map(|v| v.to_string()).unwrap_or_default()),
+            CSVAggregation::Concat(separator) => Ok(values
+                .iter()
+                .map(|v| v.as_str())
+                .collect::<Vec<&str>>()
+                .join(separator)),
+        };
+    }
+}
+
+// Adds up the numbers, starting from positive zero so an empty sum is written as 0.
+fn sum(numbers: &[f64]) -> f64 {
+    return numbers.iter().fold(0.0, |total, n| total + n);
+}
+
+impl CSVDocument {
+    // Groups the rows by the named columns and summarises each group in one row. The header holds the
+    // group columns followed by a column per aggregate, named like sum(amount). Groups appear in the
+    // order they are first seen.
+    pub fn group_by(
+        &self,
+        columns: &[&str],
+        aggregates: &[(&str, CSVAggregation)],
+    ) -> Result<CSVDocument, &'static str> {
+        let mut keys: Vec<usize> = vec![];
+        let mut targets: Vec<usize> = vec![];
+
+        for name in columns
+            .iter()
+            .chain(aggregates.iter().map(|(name, _)| name))
+        {
+            match self.find_column(name) {
+                Ok(index) if keys.len() < columns.len() => keys.push(index),
+                Ok(index) => targets.push(index),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut header = self.header.select_cells(&keys);
+
+        for (name, aggregation) in aggregates {
+            header
+                .cells
+                .push(format!("{}({})", aggregation.name(), name));
+            header.quoted.push(false);
+        }
+
+        let mut doc = CSVDocument {
+            header,
+            ..CSVDocument::new()
+        };
+
+        for (key, rows) in self.groups(&keys) {
+            let mut row = key;
+
+            for ((_, aggregation), target) in aggregates.iter().zip(targets.iter()) {
+                let cells: Vec<&String> = rows.iter().map(|r| &r.cells[*target]).collect();
+
+                match aggregation.apply(&cells) {
+                    Ok(value) => {
+                        row.cells.push(value);
+                        row.quoted.push(false);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            doc.contents.push(row);
+        }
+
+        return Ok(doc);
+    }
+
+    // Splits the rows into groups that share the cells at the indexes, in the order the groups are
+    // first seen. Each group comes with a row holding its key cells.
+    pub(crate) fn groups(&self, keys: &[usize]) -> Vec<(CSVRow, Vec<&CSVRow>)> {
+        let mut positions: HashMap<Vec<&String>, usize> = HashMap::new();
+        let mut groups: Vec<(CSVRow, Vec<&CSVRow>)> = vec![];
+
+        for row in self.contents.iter() {
+            let key: Vec<&String> = keys.iter().map(|k| &row.cells[*k]).collect();
+
+            match positions.get(&key) {
+                Some(position) => groups[*position].1.push(row),
+                None => {
+                    positions.insert(key, groups.len());
+                    groups.push((row.select_cells(keys), vec![row]));
+                }
+            }
+        }
+
+        return groups;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> CSVDocument {
+        let string: String =
+            "region,rep,amount\nNZ,ann,10\nAU,bob,5.5\nNZ,cat,\nNZ,ann,2\nAU,dan,4\n".to_string();
+
+        return CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    #[test]
+    fn test_group_by() {
+        let doc = document().group_by(
+            &["region"],
+            &[
+                ("amount", CSVAggregation::Count),
+                ("amount", CSVAggregation::Sum),
+                ("amount", CSVAggregation::Mean),
+                ("amount", CSVAggregation::Min),
+                ("amount", CSVAggregation::Max),
+                ("rep", CSVAggregation::DistinctCount),
+                ("rep", CSVAggregation::First),
+                ("rep", CSVAggregation::Last),
+                ("rep", CSVAggregation::Concat("|".to_string())),
+            ],
+        );
+
+        assert_eq!(
+            doc.unwrap().to_string(),
+            "region,count(amount),sum(amount),mean(amount),min(amount),max(amount),distinct_count(rep),first(rep),last(rep),concat(rep)\n\
+             NZ,3,12,6,2,10,2,ann,ann,ann|cat|ann\n\
+             AU,2,9.5,4.75,4,5.5,2,bob,dan,bob|dan\n"
+        );
+    }
+
+    #[test]
+    fn test_group_by_several_columns() {
+        let doc = document()
+            .group_by(&["region", "rep"], &[("amount", CSVAggregation::Sum)])
+            .unwrap();
+
+        assert_eq!(
+            doc.to_string(),
+            "region,rep,sum(amount)\nNZ,ann,12\nAU,bob,5.5\nNZ,cat,0\nAU,dan,4\n"
+        );
+    }
+
+    #[test]
+    fn test_group_by_text() {
+        let doc = document()
+            .group_by(
+                &["region"],
+                &[("rep", CSVAggregation::Min), ("rep", CSVAggregation::Max)],
+            )
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "region,min(rep),max(rep)\nNZ,ann,cat\nAU,bob,dan\n"
+        );
+
+        assert_eq!(
+            document()
+                .group_by(&["region"], &[("rep", CSVAggregation::Sum)])
+                .err(),
+            Some("The column contains a value that is not a number.")
+        );
+        assert_eq!(
+            document()
+                .group_by(&["country"], &[("rep", CSVAggregation::Count)])
+                .err(),
+            Some("The column does not exist.")
+        );
+    }
+
+    #[test]
+    fn test_group_by_not_a_number() {
+        let string: String = "key,x
+a,1
+a,NaN
+a,2
+b,inf
+b,3
+"
+        .to_string();
+        let doc = CSVDocument::parse_string(&string).unwrap();
+
+        assert_eq!(
+            doc.group_by(&["key"], &[("x", CSVAggregation::Sum)]).err(),
+            Some("The column contains a value that is not a number.")
+        );
+        assert_eq!(
+            doc.group_by(&["key"], &[("x", CSVAggregation::Mean)]).err(),
+            Some("The column contains a value that is not a number.")
+        );
+
+        // Min and Max fall back to comparing text, whatever the order of the rows.
+        let doc = doc
+            .group_by(
+                &["key"],
+                &[("x", CSVAggregation::Min), ("x", CSVAggregation::Max)],
+            )
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "key,min(x),max(x)
+a,1,NaN
+b,3,inf
+"
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVJoinKind {
+    Inner,
+    Left,
+    Right,
+    FullOuter,
+}
+
+// What happens when a column that is not a key has the same name on both sides.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CSVCollision {
+    Suffix(String, String), // Append the first suffix to the left column and the second to the right.
+    KeepLeft,               // Drop the right column.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVJoinOptions {
+    pub kind: CSVJoinKind,
+    pub left_keys: Vec<String>,
+    pub right_keys: Vec<String>, // Paired with left_keys by position.
+    pub collision: CSVCollision,
+}
+
+impl CSVJoinOptions {
+    // Joins on key columns that have the same names on both sides, suffixing colliding columns with
+    // _left and _right.
+    pub fn new(kind: CSVJoinKind, keys: &[&str]) -> CSVJoinOptions {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+
+        return CSVJoinOptions {
+            kind,
+            left_keys: keys.clone(),
+            right_keys: keys,
+            collision: CSVCollision::Suffix("_left".to_string(), "_right".to_string()),
+        };
+    }
+}
+
+impl CSVDocument {
+    // Joins the rows of two documents whose key columns are equal, using a hash table built over the
+    // right document. The result has the left columns followed by the right columns other than its
+    // keys. Rows come in the order of the left document, and the right rows without a match follow
+    // at the end, with their keys copied into the left key columns.
+    pub fn join(
+        &self,
+        other: &CSVDocument,
+        options: &CSVJoinOptions,
+    ) -> Result<CSVDocument, &'static str> {
+        if options.left_keys.is_empty() || options.left_keys.len() != options.right_keys.len() {
+            return Err("The join needs the same number of keys on both sides.");
+        }
+
+        let left_keys = match self.find_columns(&options.left_keys) {
+            Ok(keys) => keys,
+            Err(e) => return Err(e),
+        };
+
+        let right_keys = match other.find_columns(&options.right_keys) {
+            Ok(keys) => keys,
+            Err(e) => return Err(e),
+        };
+
+        // The right columns that are carried into the result.
+        let right_columns: Vec<usize> = (0..other.header.len())
+            .filter(|i| !right_keys.contains(i))
+            .collect();
+
+        let mut left_header = self.header.clone();
+        let mut right_header = other.header.select_cells(&right_columns);
+        let mut dropped: Vec<usize> = vec![];
+        let mut suffixed: Vec<String> = vec![];
+
+        for (i, name) in right_header.cells.iter_mut().enumerate() {
+            let collision = match left_header.cells.iter().position(|cell| cell == name) {
+                Some(position) => position,
+                None => continue,
+            };
+
+            match &options.collision {
+                CSVCollision::Suffix(left_suffix, right_suffix) => {
+                    left_header.cells[collision].push_str(left_suffix);
+                    name.push_str(right_suffix);
+                    suffixed.push(left_header.cells[collision].clone());
+                    suffixed.push(name.clone());
+                }
+                CSVCollision::KeepLeft => dropped.push(i),
+                CSVCollision::Error => return Err("Both documents have a column with that name."),
+            }
+        }
+
+        let right_columns: Vec<usize> = right_columns
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !dropped.contains(i))
+            .map(|(_, column)| *column)
+            .collect();
+        let kept: Vec<usize> = (0..right_header.len())
+            .filter(|i| !dropped.contains(i))
+            .collect();
+
+        left_header
+            .cells
+            .extend(right_header.select_cells(&kept).cells);
+
+        // A suffixed name can still clash with a column that already had that name.
+        for name in suffixed.iter() {
+            if left_header
+                .cells
+                .iter()
+                .filter(|cell| *cell == name)
+                .count()
+                > 1
+            {
+                return Err("A suffixed column name is already taken.");
+            }
+        }
+
+        let mut doc = CSVDocument {
+            header: left_header,
+            ..CSVDocument::new()
+        };
+
+        let mut table: HashMap<Vec<&String>, Vec<usize>> = HashMap::new();
+
+        for (i, row) in other.contents.iter().enumerate() {
+            let key: Vec<&String> = right_keys.iter().map(|k| &row.cells[*k]).collect();
+            table.entry(key).or_default().push(i);
+        }
+
+        let keep_left = options.kind == CSVJoinKind::Left || options.kind == CSVJoinKind::FullOuter;
+        let keep_right =
+            options.kind == CSVJoinKind::Right || options.kind == CSVJoinKind::FullOuter;
+        let mut matched = vec![false; other.contents.len()];
+
+        for row in self.contents.iter() {
+            let key: Vec<&String> = left_keys.iter().map(|k| &row.cells[*k]).collect();
+
+            match table.get(&key) {
+                Some(matches) => {
+                    for i in matches {
+                        matched[*i] = true;
+                        doc.contents.push(joined_row(
+                            row,
+                            &other.contents[*i].select_cells(&right_columns),
+                        ));
+                    }
+                }
+                None if keep_left => {
+                    doc.contents
+                        .push(joined_row(row, &empty_row(right_columns.len())));
+                }
+                None => (),
+            }
+        }
+
+        if keep_right {
+            for (i, row) in other.contents.iter().enumerate() {
+                if matched[i] {
+                    continue;
+                }
+
+                let mut left = empty_row(self.header.len());
+
+                for (left_key, right_key) in left_keys.iter().zip(right_keys.iter()) {
+                    left.cells[*left_key] = row.cells[*right_key].clone();
+                    left.quoted[*left_key] = row.is_quoted(*right_key);
+                }
+
+                doc.contents
+                    .push(joined_row(&left, &row.select_cells(&right_columns)));
+            }
+        }
+
+        return Ok(doc);
+    }
+}
+
+fn empty_row(width: usize) -> CSVRow {
+    return CSVRow {
+        cells: vec![String::new(); width],
+        quoted: vec![false; width],
+    };
+}
+
+fn joined_row(left: &CSVRow, right: &CSVRow) -> CSVRow {
+    let mut row = left.clone();
+    row.quoted.resize(row.cells.len(), false); // Missing flags count as unquoted.
+    row.cells.extend(right.cells.iter().cloned());
+    row.quoted.extend(right.quoted.iter().cloned());
+    return row;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(string: &str) -> CSVDocument {
+        return CSVDocument::parse_string(&string.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    fn customers() -> CSVDocument {
+        return parse("id,name,note\n1,ann,vip\n2,bob,\n3,cat,new\n");
+    }
+
+    fn invoices() -> CSVDocument {
+        return parse("id,amount,note\n1,10,paid\n1,15,due\n4,7,paid\n");
+    }
+
+    #[test]
+    fn test_join_kinds() {
+        let left = customers();
+        let right = invoices();
+        let join = |kind| {
+            left.join(&right, &CSVJoinOptions::new(kind, &["id"]))
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            join(CSVJoinKind::Inner),
+            "id,name,note_left,amount,note_right\n1,ann,vip,10,paid\n1,ann,vip,15,due\n"
+        );
+        assert_eq!(
+            join(CSVJoinKind::Left),
+            "id,name,note_left,amount,note_right\n1,ann,vip,10,paid\n1,ann,vip,15,due\n2,bob,,,\n3,cat,new,,\n"
+        );
+        assert_eq!(
+            join(CSVJoinKind::Right),
+            "id,name,note_left,amount,note_right\n1,ann,vip,10,paid\n1,ann,vip,15,due\n4,,,7,paid\n"
+        );
+        assert_eq!(
+            join(CSVJoinKind::FullOuter),
+            "id,name,note_left,amount,note_right\n1,ann,vip,10,paid\n1,ann,vip,15,due\n2,bob,,,\n3,cat,new,,\n4,,,7,paid\n"
+        );
+    }
+
+    #[test]
+    fn test_join_collisions() {
+        let left = customers();
+        let right = invoices();
+
+        let mut options = CSVJoinOptions::new(CSVJoinKind::Inner, &["id"]);
+        options.collision = CSVCollision::KeepLeft;
+        assert_eq!(
+            left.join(&right, &options).unwrap().to_string(),
+            "id,name,note,amount\n1,ann,vip,10\n1,ann,vip,15\n"
+        );
+
+        options.collision = CSVCollision::Error;
+        assert_eq!(
+            left.join(&right, &options).err(),
+            Some("Both documents have a column with that name.")
+        );
+
+        options.collision = CSVCollision::Suffix("_left".to_string(), "_right".to_string());
+        assert_eq!(
+            parse("id,note,note_left\n1,a,b\n")
+                .join(&parse("id,note\n1,c\n"), &options)
+                .err(),
+            Some("A suffixed column name is already taken.")
+        );
+    }
+
+    #[test]
+    fn test_join_different_keys() {
+        let left = parse("customer,region,name\n1,NZ,ann\n1,AU,amy\n2,NZ,bob\n");
+        let right = parse("region,id,total\nNZ,1,10\nNZ,2,20\nAU,2,30\n");
+
+        let mut options = CSVJoinOptions::new(CSVJoinKind::FullOuter, &[]);
+        options.left_keys = vec!["customer".to_string(), "region".to_string()];
+        options.right_keys = vec!["id".to_string(), "region".to_string()];
+
+        assert_eq!(
+            left.join(&right, &options).unwrap().to_string(),
+            "customer,region,name,total\n1,NZ,ann,10\n1,AU,amy,\n2,NZ,bob,20\n2,AU,,30\n"
+        );
+
+        options.right_keys.pop();
+        assert_eq!(
+            left.join(&right, &options).err(),
+            Some("The join needs the same number of keys on both sides.")
+        );
+    }
+
+    #[test]
+    fn test_join_large() {
+        let mut left = String::from("id,left\n");
+        let mut right = String::from("id,right\n");
+
+        for i in 0..100000 {
+            left.push_str(&format!("{},{}\n", i, i * 2));
+            right.push_str(&format!("{},{}\n", 99999 - i, i));
+        }
+
+        let doc = parse(&left)
+            .join(
+                &parse(&right),
+                &CSVJoinOptions::new(CSVJoinKind::Inner, &["id"]),
+            )
+            .unwrap();
+
+        assert_eq!(doc.row_count(), 100000);
+        assert_eq!(doc.contents[5].cells, vec!["5", "10", "99994"]);
+    }
+}
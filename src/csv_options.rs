@@ -0,0 +1,117 @@
+use crate::csv_dialect::CSVDialect;
+use crate::csv_encoding::CSVEncoding;
+
+// The byte order mark that Excel and other Windows tools place at the start of UTF-8 files.
+pub const UTF8_BOM: char = '\u{feff}';
+
+// Limits that stop untrusted input from using unbounded memory. Sizes are in bytes of UTF-8 text and
+// None means unlimited. Each limit fails with its own error message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVLimits {
+    pub max_field_size: Option<usize>,
+    pub max_record_size: Option<usize>,
+    pub max_columns: Option<usize>,
+    pub max_rows: Option<usize>, // The number of rows excluding the header.
+    pub max_total_bytes: Option<usize>,
+}
+
+impl CSVLimits {
+    pub const FIELD_SIZE_EXCEEDED: &'static str = "The field exceeds the maximum field size.";
+    pub const RECORD_SIZE_EXCEEDED: &'static str = "The record exceeds the maximum record size.";
+    pub const COLUMNS_EXCEEDED: &'static str = "The record exceeds the maximum number of columns.";
+    pub const ROWS_EXCEEDED: &'static str = "The document exceeds the maximum number of rows.";
+    pub const TOTAL_BYTES_EXCEEDED: &'static str = "The input exceeds the maximum total size.";
+
+    pub fn new() -> CSVLimits {
+        return CSVLimits {
+            max_field_size: None,
+            max_record_size: None,
+            max_columns: None,
+            max_rows: None,
+            max_total_bytes: None,
+        };
+    }
+
+    pub(crate) fn exceeds(limit: Option<usize>, value: usize) -> bool {
+        return match limit {
+            Some(limit) => value > limit,
+            None => false,
+        };
+    }
+}
+
+// What the row methods of CSVDocument do with a row that is not as wide as the header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVWidthPolicy {
+    Strict,        // Reject the row.
+    Pad,           // Pad a short row with empty cells and reject a long one.
+    PadOrTruncate, // Pad a short row and drop the extra cells of a long one.
+    Allow,         // Accept rows of any width.
+}
+
+// Options that control how input is turned into a CSVDocument.
+#[derive(Clone)]
+pub struct CSVParseOptions {
+    pub dialect: CSVDialect,
+    pub encoding: Option<CSVEncoding>, // The encoding of byte input, None detects it from the input.
+    pub preserve_comments: bool, // Keep comment lines in CSVDocument::comments instead of discarding them.
+    pub skip_empty_lines: bool,  // Drop blank lines instead of keeping them as rows without cells.
+    pub limits: CSVLimits,
+}
+
+impl CSVParseOptions {
+    pub fn new() -> CSVParseOptions {
+        return CSVParseOptions::from_dialect(CSVDialect::new());
+    }
+
+    pub fn from_dialect(dialect: CSVDialect) -> CSVParseOptions {
+        return CSVParseOptions {
+            dialect,
+            encoding: None,
+            preserve_comments: false,
+            skip_empty_lines: true,
+            limits: CSVLimits::new(),
+        };
+    }
+}
+
+// How cells that a spreadsheet would run as a formula are neutralised when writing. Following the
+// OWASP guidance on CSV injection, a cell starting with =, +, -, @, tab or carriage return is
+// prefixed with a single quote so it is shown as text, and can also be wrapped in quotes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVSanitize {
+    None,
+    Prefix,
+    PrefixAndQuote,
+}
+
+impl CSVSanitize {
+    const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+    // Returns true if a spreadsheet could treat the cell as a formula.
+    pub fn is_formula(cell: &str) -> bool {
+        return cell.starts_with(&CSVSanitize::FORMULA_PREFIXES[..]);
+    }
+}
+
+// Options that control how a CSVDocument is serialized.
+#[derive(Clone)]
+pub struct CSVWriteOptions {
+    pub dialect: CSVDialect,
+    pub bom: bool,                    // Emit a byte order mark before the header row.
+    pub encoding: CSVEncoding,        // The encoding used when writing bytes.
+    pub sanitize: CSVSanitize,        // Neutralise cells that could run as spreadsheet formulas.
+    pub numeric_columns: Vec<String>, // Header names whose numeric cells, such as -5, are not sanitized.
+}
+
+impl CSVWriteOptions {
+    pub fn new() -> CSVWriteOptions {
+        return CSVWriteOptions {
+            dialect: CSVDialect::new(),
+            bom: false,
+            encoding: CSVEncoding::Utf8,
+            sanitize: CSVSanitize::None,
+            numeric_columns: vec![],
+        };
+    }
+}
@@ -0,0 +1,319 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_encoding::CSVEncoding;
+use crate::csv_options::CSVLimits;
+use crate::csv_options::CSVParseOptions;
+use crate::csv_options::UTF8_BOM;
+use crate::csv_row::CSVRow;
+use crate::csv_tokenizer::CSVTokenizer;
+use std::collections::VecDeque;
+use std::io::Read;
+
+const CHUNK_SIZE: usize = 8192;
+
+// Reads the rows of CSV from a stream one at a time, so input larger than memory can be processed.
+// It parses exactly like CSVDocument::parse_bytes, except that comments are always dropped.
+pub struct CSVReader<R: Read> {
+    input: R,
+    options: CSVParseOptions,
+    encoding: Option<CSVEncoding>, // Decided from the first chunk when the options do not declare it.
+    tokenizer: CSVTokenizer,
+    staged: CSVDocument, // Holds the header and the rows parsed from the latest chunk.
+    width: Option<usize>,
+    ready: VecDeque<CSVRow>,
+    undecoded: Vec<u8>, // The start of a character split across two chunks.
+    started: bool,
+    finished: bool,
+    error: Option<&'static str>, // Reported once the rows parsed before it have been returned.
+    total_bytes: usize,
+    row_count: usize,
+    max_rows: Option<usize>,
+}
+
+impl<R: Read> CSVReader<R> {
+    // Creates a reader and reads up to the end of the header, if the dialect has one.
+    pub fn new(input: R, options: &CSVParseOptions) -> Result<CSVReader<R>, &'static str> {
+        // Rows are counted here since they are drained from the staging document as they arrive.
+        let mut staged_options = options.clone();
+        staged_options.preserve_comments = false;
+        staged_options.limits.max_rows = None;
+
+        let mut reader = CSVReader {
+            input,
+            tokenizer: CSVTokenizer::with_limits(&options.dialect, &options.limits),
+            options: staged_options,
+            encoding: options.encoding,
+            staged: CSVDocument::new(),
+            width: None,
+            ready: VecDeque::new(),
+            undecoded: vec![],
+            started: false,
+            finished: false,
+            error: None,
+            total_bytes: 0,
+            row_count: 0,
+            max_rows: options.limits.max_rows,
+        };
+
+        while options.dialect.has_header && reader.width.is_none() && !reader.finished {
+            reader.fill();
+        }
+
+        if let (Some(e), None) = (reader.error, reader.width) {
+            return Err(e);
+        }
+
+        return Ok(reader);
+    }
+
+    pub fn header(&self) -> &CSVRow {
+        return &self.staged.header;
+    }
+
+    // True when the input started with a byte order mark.
+    pub fn has_bom(&self) -> bool {
+        return self.staged.has_bom;
+    }
+
+    pub fn encoding(&self) -> CSVEncoding {
+        return self.encoding.unwrap_or(CSVEncoding::Utf8);
+    }
+
+    // Returns the next row, or None at the end of the input.
+    pub fn next_row(&mut self) -> Result<Option<CSVRow>, &'static str> {
+        while self.ready.is_empty() && !self.finished {
+            self.fill();
+        }
+
+        return match (self.ready.pop_front(), self.error.take()) {
+            (Some(row), error) => {
+                self.error = error;
+                Ok(Some(row))
+            }
+            (None, Some(e)) => Err(e),
+            (None, None) => Ok(None),
+        };
+    }
+
+    // Reads the next chunk and moves the rows it completes to the ready queue. After an error the
+    // rows before it are still handed out, then the error is reported and reading stops.
+    fn fill(&mut self) {
+        let result = self.parse_chunk();
+
+        for row in self.staged.contents.drain(..) {
+            if CSVLimits::exceeds(self.max_rows, self.row_count + 1) {
+                self.error = Some(CSVLimits::ROWS_EXCEEDED);
+                break;
+            }
+
+            self.row_count += 1;
+            self.ready.push_back(row);
+        }
+
+        if let Err(e) = result {
+            self.error = self.error.or(Some(e));
+        }
+
+        if self.error.is_some() {
+            self.finished = true;
+        }
+    }
+
+    fn parse_chunk(&mut self) -> Result<(), &'static str> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+
+        let read = match self.input.read(&mut chunk) {
+            Ok(read) => read,
+            Err(_) => return Err("The input could not be read."),
+        };
+
+        self.total_bytes += read;
+
+        if CSVLimits::exceeds(self.options.limits.max_total_bytes, self.total_bytes) {
+            return Err(CSVLimits::TOTAL_BYTES_EXCEEDED);
+        }
+
+        self.undecoded.extend_from_slice(&chunk[..read]);
+
+        // Detection needs a reasonable sample, so wait for a full chunk or the end of the input.
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None if read != 0 && self.undecoded.len() < CHUNK_SIZE => return Ok(()),
+            None => CSVEncoding::detect(&self.undecoded),
+        };
+        self.encoding = Some(encoding);
+
+        // At the end of the input everything left has to decode.
+        let complete = if read == 0 {
+            self.undecoded.len()
+        } else {
+            encoding.complete_prefix(&self.undecoded)
+        };
+
+        let text = match encoding.decode(&self.undecoded[..complete]) {
+            Ok(text) => text,
+            Err(e) => return Err(e),
+        };
+        self.undecoded.drain(..complete);
+
+        for c in text.chars() {
+            if !self.started {
+                self.started = true;
+
+                if c == UTF8_BOM {
+                    self.staged.has_bom = true;
+                    continue;
+                }
+            }
+
+            match self.tokenizer.push(c) {
+                Ok(Some(token)) => {
+                    if let Err(e) = self.staged.add_token(token, &self.options, &mut self.width) {
+                        return Err(e);
+                    }
+                }
+                Ok(None) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if read == 0 {
+            self.finished = true;
+
+            match self.tokenizer.finish() {
+                Ok(Some(token)) => {
+                    if let Err(e) = self.staged.add_token(token, &self.options, &mut self.width) {
+                        return Err(e);
+                    }
+                }
+                Ok(None) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+impl<R: Read> Iterator for CSVReader<R> {
+    type Item = Result<CSVRow, &'static str>;
+
+    fn next(&mut self) -> Option<Result<CSVRow, &'static str>> {
+        return match self.next_row() {
+            Ok(Some(row)) => Some(Ok(row)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_options::CSVLimits;
+
+    // Hands out the input a few bytes at a time so characters and records are split across reads.
+    struct Trickle<'a> {
+        input: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.step.min(self.input.len()).min(buf.len());
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            return Ok(n);
+        }
+    }
+
+    fn read_all(input: &[u8], step: usize, options: &CSVParseOptions) -> CSVDocument {
+        let mut reader = CSVReader::new(Trickle { input, step }, options).unwrap();
+        let mut doc = CSVDocument {
+            header: reader.header().clone(),
+            has_bom: reader.has_bom(),
+            ..CSVDocument::new()
+        };
+
+        while let Some(row) = reader.next_row().unwrap() {
+            doc.contents.push(row);
+        }
+
+        doc.encoding = reader.encoding();
+        return doc;
+    }
+
+    #[test]
+    fn test_reader_matches_parse_bytes() {
+        let text = "\u{feff}name,note\ncaf\u{e9},\"line\r\none\"\n\u{1f600},\"a,b\"\n\nlast,row";
+        let options = CSVParseOptions::new();
+
+        for encoding in &[
+            CSVEncoding::Utf8,
+            CSVEncoding::Utf16LE,
+            CSVEncoding::Utf16BE,
+        ] {
+            let bytes = encoding.encode(text).unwrap();
+            let expected = CSVDocument::parse_bytes(&bytes, &options).unwrap();
+
+            for step in 1..8 {
+                let doc = read_all(&bytes, step, &options);
+
+                assert_eq!(doc.header, expected.header);
+                assert_eq!(doc.contents, expected.contents);
+                assert_eq!(doc.has_bom, expected.has_bom);
+                assert_eq!(doc.encoding, *encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reader_errors() {
+        let options = CSVParseOptions::new();
+
+        let mut reader = CSVReader::new(&b"a,b\n1,2\n3\n4,5\n"[..], &options).unwrap();
+        assert_eq!(reader.next_row().unwrap().unwrap().cells, vec!["1", "2"]);
+        assert_eq!(
+            reader.next_row(),
+            Err("The amount of cells in the row was invalid.")
+        );
+        assert_eq!(reader.next_row(), Ok(None));
+
+        let results: Vec<Result<CSVRow, &str>> = CSVReader::new(&b"a\n\"1\n"[..], &options)
+            .unwrap()
+            .collect();
+        assert_eq!(results, vec![Err("Unterminated quotes in cell.")]);
+
+        let mut options = CSVParseOptions::new();
+        options.limits.max_rows = Some(1);
+        let results: Vec<Result<CSVRow, &str>> = CSVReader::new(&b"a\n1\n2\n"[..], &options)
+            .unwrap()
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok(CSVRow::parse_line_new(&"1".to_string()).unwrap()),
+                Err(CSVLimits::ROWS_EXCEEDED)
+            ]
+        );
+
+        let mut options = CSVParseOptions::new();
+        options.limits.max_field_size = Some(2);
+        assert_eq!(
+            CSVReader::new(&b"abc\n1\n"[..], &options).err(),
+            Some(CSVLimits::FIELD_SIZE_EXCEEDED)
+        );
+    }
+
+    #[test]
+    fn test_reader_without_header() {
+        let mut options = CSVParseOptions::new();
+        options.dialect.has_header = false;
+
+        let rows: Vec<Vec<String>> = CSVReader::new(&b"1,2\n3,4\n"[..], &options)
+            .unwrap()
+            .map(|row| row.unwrap().cells)
+            .collect();
+
+        assert_eq!(rows, vec![vec!["1", "2"], vec!["3", "4"]]);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_group::CSVAggregation;
+use crate::csv_row::CSVRow;
+use std::collections::HashMap;
+
+impl CSVDocument {
+    // Turns long data into wide data. Rows are grouped by the index columns, every distinct value of
+    // column_col becomes a column, and each cell summarises the value_col cells of its group and
+    // column. Groups and columns keep the order they are first seen in, and a combination without
+    // any rows is left empty.
+    pub fn pivot(
+        &self,
+        index_cols: &[&str],
+        column_col: &str,
+        value_col: &str,
+        agg: CSVAggregation,
+    ) -> Result<CSVDocument, &'static str> {
+        let keys = match self.find_columns(index_cols) {
+            Ok(keys) => keys,
+            Err(e) => return Err(e),
+        };
+
+        let (column, value) = match (self.find_column(column_col), self.find_column(value_col)) {
+            (Ok(column), Ok(value)) => (column, value),
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        };
+
+        let mut header = self.header.select_cells(&keys);
+        let mut positions: HashMap<&String, usize> = HashMap::new();
+
+        for row in self.contents.iter() {
+            let name = &row.cells[column];
+
+            if positions.contains_key(name) {
+                continue;
+            } else if header.cells.contains(name) {
+                return Err("A pivoted column has the same name as an index column.");
+            }
+
+            positions.insert(name, positions.len());
+            header.cells.push(name.clone());
+            header.quoted.push(false);
+        }
+
+        let mut doc = CSVDocument {
+            header,
+            ..CSVDocument::new()
+        };
+
+        for (mut row, rows) in self.groups(&keys) {
+            let mut cells: Vec<Vec<&String>> = vec![vec![]; positions.len()];
+
+            for r in rows {
+                cells[positions[&r.cells[column]]].push(&r.cells[value]);
+            }
+
+            for values in cells {
+                let cell = if values.is_empty() {
+                    Ok(String::new())
+                } else {
+                    agg.apply(&values)
+                };
+
+                match cell {
+                    Ok(cell) => {
+                        row.cells.push(cell);
+                        row.quoted.push(false);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            doc.contents.push(row);
+        }
+
+        return Ok(doc);
+    }
+
+    // Swaps rows and columns, reading the header as the first row. Short rows are padded with empty
+    // cells. The first row of the result becomes the header when first_column_as_header is set,
+    // otherwise the result has no header and every row is content.
+    pub fn transpose(&self, first_column_as_header: bool) -> CSVDocument {
+        let mut records: Vec<&CSVRow> = vec![];
+
+        if !self.header.is_empty() {
+            records.push(&self.header);
+        }
+
+        records.extend(self.contents.iter());
+
+        let width = records.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut doc = CSVDocument::new();
+
+        for column in 0..width {
+            let mut row = CSVRow::new();
+
+            for record in records.iter() {
+                row.cells
+                    .push(record.cells.get(column).cloned().unwrap_or_default());
+                row.quoted.push(record.is_quoted(column));
+            }
+
+            if first_column_as_header && column == 0 {
+                doc.header = row;
+            } else {
+                doc.contents.push(row);
+            }
+        }
+
+        return doc;
+    }
+
+    // Turns wide data into long data. Each row becomes one row per value column, holding the id
+    // columns followed by a variable column with the value column's name and a value column with its
+    // cell. An empty list of value columns melts every column that is not an id column.
+    pub fn melt(&self, id_cols: &[&str], value_cols: &[&str]) -> Result<CSVDocument, &'static str> {
+        // Checked here as well since find_columns does not run without id columns.
+        if let Err(e) = self.check_columns() {
+            return Err(e);
+        }
+
+        let ids = match self.find_columns(id_cols) {
+            Ok(ids) => ids,
+            Err(e) => return Err(e),
+        };
+
+        let values = if value_cols.is_empty() {
+            Ok((0..self.header.len())
+                .filter(|i| !ids.contains(i))
+                .collect())
+        } else {
+            self.find_columns(value_cols)
+        };
+
+        let values = match values {
+            Ok(values) => values,
+            Err(e) => return Err(e),
+        };
+
+        let mut header = self.header.select_cells(&ids);
+
+        if header
+            .cells
+            .iter()
+            .any(|name| name == "variable" || name == "value")
+        {
+            return Err("An id column is named variable or value.");
+        }
+
+        header.cells.push("variable".to_string());
+        header.cells.push("value".to_string());
+        header.quoted.extend([false, false]);
+
+        let mut doc = CSVDocument {
+            header,
+            ..CSVDocument::new()
+        };
+
+        for row in self.contents.iter() {
+            for value in values.iter() {
+                let mut melted: CSVRow = row.select_cells(&ids);
+                melted.cells.push(self.header.cells[*value].clone());
+                melted.quoted.push(self.header.is_quoted(*value));
+                melted.cells.push(row.cells[*value].clone());
+                melted.quoted.push(row.is_quoted(*value));
+                doc.contents.push(melted);
+            }
+        }
+
+        return Ok(doc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_options::CSVWriteOptions;
+
+    fn parse(string: &str) -> CSVDocument {
+        return CSVDocument::parse_string(&string.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+    }
+
+    #[test]
+    fn test_pivot() {
+        let doc = parse("region,month,amount\nNZ,jan,10\nNZ,feb,5\nAU,jan,7\nNZ,jan,3\n");
+
+        assert_eq!(
+            doc.pivot(&["region"], "month", "amount", CSVAggregation::Sum)
+                .unwrap()
+                .to_string(),
+            "region,jan,feb\nNZ,13,5\nAU,7,\n"
+        );
+        assert_eq!(
+            doc.pivot(&[], "region", "amount", CSVAggregation::Count)
+                .unwrap()
+                .to_string(),
+            "NZ,AU\n3,1\n"
+        );
+        assert_eq!(
+            parse("key,name,value\nx,key,1\n")
+                .pivot(&["key"], "name", "value", CSVAggregation::First)
+                .err(),
+            Some("A pivoted column has the same name as an index column.")
+        );
+    }
+
+    #[test]
+    fn test_transpose() {
+        let doc = parse("field,run1,run2\ntemp,21.5,22\n\"\",1,2\n");
+
+        let transposed = doc.transpose(true);
+        assert_eq!(
+            transposed.to_string(),
+            "field,temp,\"\"\nrun1,21.5,1\nrun2,22,2\n"
+        );
+        assert_eq!(transposed.transpose(true).to_string(), doc.to_string());
+
+        let transposed = doc.transpose(false);
+        assert!(transposed.header.is_empty());
+        assert_eq!(transposed.row_count(), 3);
+        assert_eq!(transposed.contents[0].cells, vec!["field", "temp", ""]);
+
+        // A document without a header is written without a blank header line.
+        assert_eq!(
+            transposed.to_string(),
+            "field,temp,\"\"\nrun1,21.5,1\nrun2,22,2\n"
+        );
+    }
+
+    #[test]
+    fn test_transpose_ragged() {
+        let mut options = CSVWriteOptions::new();
+        options.dialect.has_header = false;
+
+        let doc = CSVDocument {
+            contents: vec![
+                CSVRow::parse_line_new(&"1,2,3".to_string()).unwrap(),
+                CSVRow::parse_line_new(&"4".to_string()).unwrap(),
+                CSVRow::parse_line_new(&"5,6".to_string()).unwrap(),
+            ],
+            ..CSVDocument::new()
+        };
+
+        assert_eq!(
+            doc.transpose(false).to_string_with_options(&options),
+            "1,4,5\n2,,6\n3,,\n"
+        );
+    }
+
+    #[test]
+    fn test_melt() {
+        let doc = parse("name,jan,feb\nann,1,2\nbob,3,\n");
+
+        let long = doc.melt(&["name"], &[]).unwrap();
+        assert_eq!(
+            long.to_string(),
+            "name,variable,value\nann,jan,1\nann,feb,2\nbob,jan,3\nbob,feb,\n"
+        );
+
+        assert_eq!(
+            doc.melt(&["name"], &["feb"]).unwrap().to_string(),
+            "name,variable,value\nann,feb,2\nbob,feb,\n"
+        );
+
+        // Melting and pivoting back gives the original document.
+        assert_eq!(
+            long.pivot(&["name"], "variable", "value", CSVAggregation::First)
+                .unwrap()
+                .to_string(),
+            doc.to_string()
+        );
+
+        let mut ragged = doc.clone();
+        ragged.contents[1].cells.pop();
+        assert_eq!(
+            ragged.melt(&[], &[]).err(),
+            Some("The amount of cells in the row was invalid.")
+        );
+    }
+}
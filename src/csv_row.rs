@@ -1,3 +1,9 @@
+use crate::csv_dialect::CSVDialect;
+use crate::csv_options::CSVSanitize;
+use crate::csv_options::CSVWriteOptions;
+use crate::csv_options::UTF8_BOM;
+use crate::csv_tokenizer::CSVTokenizer;
+
 // Allows for an object to be created from a CSVRow
 pub trait CSVParseable {
     fn construct_from_row(row: &CSVRow) -> Result<Self, &'static str>
@@ -5,55 +11,184 @@ pub trait CSVParseable {
         Self: Sized;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct CSVRow {
     pub cells: Vec<String>,
+    pub quoted: Vec<bool>, // Whether each parsed cell was quoted. Missing entries count as unquoted.
 }
 
 impl CSVRow {
     pub fn new() -> CSVRow {
-        return CSVRow { cells: vec![] };
+        return CSVRow {
+            cells: vec![],
+            quoted: vec![],
+        };
     }
 
     pub fn len(&self) -> usize {
         return self.cells.len();
     }
 
+    pub fn is_empty(&self) -> bool {
+        return self.cells.is_empty();
+    }
+
+    pub fn is_quoted(&self, index: usize) -> bool {
+        return self.quoted.get(index) == Some(&true);
+    }
+
+    // Returns the cells with empty unquoted cells as None, the way Postgres treats NULL in CSV.
+    pub fn nullable_cells(&self) -> Vec<Option<&String>> {
+        let mut cells = vec![];
+
+        for i in 0..self.cells.len() {
+            if self.cells[i].is_empty() && !self.is_quoted(i) {
+                cells.push(None);
+            } else {
+                cells.push(Some(&self.cells[i]));
+            }
+        }
+
+        return cells;
+    }
+
+    // Inserts an unquoted cell, keeping the quoted flags of the cells after it lined up.
+    pub(crate) fn insert_cell(&mut self, index: usize, cell: String) {
+        if index < self.quoted.len() {
+            self.quoted.insert(index, false);
+        }
+
+        self.cells.insert(index, cell);
+    }
+
+    pub(crate) fn remove_cell(&mut self, index: usize) -> String {
+        if index < self.quoted.len() {
+            self.quoted.remove(index);
+        }
+
+        return self.cells.remove(index);
+    }
+
+    // Builds a row from the cells at the indexes, in that order, along with their quoted flags.
+    pub(crate) fn select_cells(&self, indexes: &[usize]) -> CSVRow {
+        let mut row = CSVRow::new();
+
+        for index in indexes {
+            row.cells.push(self.cells[*index].clone());
+            row.quoted.push(self.is_quoted(*index));
+        }
+
+        return row;
+    }
+
+    #[allow(clippy::inherent_to_string)]
     pub fn to_string(&self) -> String {
+        return self.to_string_with_dialect(&CSVDialect::new());
+    }
+
+    pub fn to_string_with_dialect(&self, dialect: &CSVDialect) -> String {
+        return self.write(dialect, CSVSanitize::None, &[]);
+    }
+
+    // Serializes the row with the dialect and formula sanitization of the write options. The numeric
+    // column allowlist needs a header, so it only applies when a CSVDocument is written.
+    pub fn to_string_with_options(&self, options: &CSVWriteOptions) -> String {
+        return self.write(&options.dialect, options.sanitize, &[]);
+    }
+
+    // Cells flagged in numeric are left alone by the sanitizer when they parse as a number.
+    pub(crate) fn write(
+        &self,
+        dialect: &CSVDialect,
+        sanitize: CSVSanitize,
+        numeric: &[bool],
+    ) -> String {
         let mut str = String::new();
 
-        for cell in self.cells.clone() {
-            let mut working = cell.clone();
+        // Escaping with the quote character is the same as doubling quotes.
+        let escape = dialect.escape.filter(|escape| *escape != dialect.quote);
 
-            // Check if the cell contains whitespace, new lines, commas or quotes
-            let mut quoted = cell.contains('\n') || cell.contains(',');
+        for (index, cell) in self.cells.iter().enumerate() {
+            let mut working = String::new();
 
-            if working.contains('"') {
-                quoted = true;
+            // The cell is not trimmed, since a leading tab or carriage return is itself a trigger.
+            let is_number = numeric.get(index) == Some(&true)
+                && cell.parse::<f64>().is_ok_and(|n| n.is_finite());
+            let sanitized =
+                sanitize != CSVSanitize::None && !is_number && CSVSanitize::is_formula(cell);
+            let prefixed: String;
 
-                // We need to add another quote to one side of the existing quote
-                let mut count = 0;
-                let chars: Vec<char> = working.chars().collect();
+            let cell = if sanitized {
+                prefixed = format!("'{}", cell);
+                &prefixed
+            } else {
+                cell
+            };
+
+            // Check if the cell contains whitespace, new lines, delimiters or quotes. When escaping
+            // new lines, tabs, delimiters and quotes are escaped instead of quoted, but whitespace
+            // at either end is still quoted so it survives trimming.
+            let mut quoted = match escape {
+                Some(_) => {
+                    cell.chars()
+                        .any(|c| c.is_whitespace() && c != '\t' && c != '\r' && c != '\n')
+                        || cell.starts_with(char::is_whitespace)
+                        || cell.ends_with(char::is_whitespace)
+                }
+                None => {
+                    cell.contains(dialect.delimiter)
+                        || cell.contains(dialect.quote)
+                        || cell.contains(char::is_whitespace)
+                }
+            };
+
+            // A leading comment character or byte order mark would be mistaken for a comment line or
+            // a mark on the first line, and a lone empty cell would be read back as a blank line.
+            quoted = quoted
+                || (index == 0 && dialect.comment.is_some_and(|c| cell.starts_with(c)))
+                || cell.starts_with(UTF8_BOM)
+                || (cell.is_empty() && self.cells.len() == 1)
+                || (sanitized && sanitize == CSVSanitize::PrefixAndQuote);
+
+            for c in cell.chars() {
+                match escape {
+                    Some(escape) => {
+                        let sequence = match c {
+                            '\t' => Some('t'),
+                            '\r' => Some('r'),
+                            '\n' => Some('n'),
+                            c if c == escape || c == dialect.quote || c == dialect.delimiter => {
+                                Some(c)
+                            }
+                            _ => None,
+                        };
+
+                        match sequence {
+                            Some(sequence) => {
+                                working.push(escape);
+                                working.push(sequence);
+                            }
+                            None => working.push(c),
+                        }
+                    }
+                    None => {
+                        // A quote inside the cell is escaped by another quote
+                        if c == dialect.quote {
+                            working.push(c);
+                        }
 
-                for i in 0..chars.len() {
-                    if chars[i] == '"' {
-                        working.insert(i + 1 + count, '"'); // Because the length of the original string changes as we add " we need to adjust for this by counting how many we have added
-                        count += 1;
+                        working.push(c);
                     }
                 }
             }
 
-            match working.find(char::is_whitespace) {
-                None => (),
-                Some(_) => quoted = true,
+            // An empty cell that was quoted stays quoted so it is not read back as NULL.
+            if quoted || (cell.is_empty() && self.is_quoted(index)) {
+                working.insert(0, dialect.quote);
+                working.push(dialect.quote);
             }
 
-            if quoted {
-                working.insert(0, '"');
-                working.push('"');
-            }
-
-            working.push(',');
+            working.push(dialect.delimiter);
 
             str.push_str(&working);
         }
@@ -64,112 +199,87 @@ impl CSVRow {
     }
 
     pub fn parse_line(&mut self, line: &String) -> Result<(), &'static str> {
-        let mut in_quotes = false;
-        let mut current_cell = String::new();
-        let mut cells: Vec<String> = Vec::new();
-
-        let characters: Vec<char> = line.chars().collect();
-
-        let mut i = 0;
-
-        while i < characters.len() {
-            if characters[i] == '"' && !in_quotes {
-                in_quotes = true;
-                current_cell = String::new(); // Ignore anything that was there before the quotes.
-            } else if characters[i] == '"' && in_quotes {
-                if i + 1 >= characters.len() {
-                    in_quotes = false;
-                    cells.push(current_cell);
-                    current_cell = String::new();
-                } else {
-                    let p = characters[i + 1];
-
-                    if p != '\0' && p != '"' {
-                        in_quotes = false;
-                    } else if p == '"' {
-                        i += 1;
-                    }
-                }
-            } else {
-                if characters[i] == ',' && !in_quotes {
-                    cells.push(current_cell);
-                    current_cell = String::new();
-                } else {
-                    current_cell.push(characters[i]);
-                }
-            }
-
-            i += 1;
+        match CSVRow::parse_line_new(line) {
+            Ok(row) => *self = row,
+            Err(e) => return Err(e),
         }
 
-        if in_quotes {
-            return Err("Unterminated quotes in cell.");
-        }
+        return Ok(());
+    }
 
-        if current_cell.len() != 0 {
-            cells.push(current_cell);
-        }
+    pub fn parse_line_new(line: &String) -> Result<CSVRow, &'static str> {
+        return CSVRow::parse_line_with_dialect(line, &CSVDialect::new());
+    }
 
-        self.cells = cells;
+    #[allow(clippy::ptr_arg)]
+    pub fn parse_line_with_dialect(
+        line: &String,
+        dialect: &CSVDialect,
+    ) -> Result<CSVRow, &'static str> {
+        return CSVTokenizer::parse_line(line, dialect);
+    }
+}
 
-        return Ok(());
+// Rows are equal when their cells are, and each cell is quoted in both or neither. A missing quoted
+// entry counts as unquoted, so a row built by hand equals the same row parsed without quotes.
+impl PartialEq for CSVRow {
+    fn eq(&self, other: &CSVRow) -> bool {
+        return self.cells == other.cells
+            && (0..self.cells.len()).all(|i| self.is_quoted(i) == other.is_quoted(i));
     }
+}
 
-    pub fn parse_line_new(line: &String) -> Result<CSVRow, &'static str> {
-        let mut in_quotes = false;
-        let mut current_cell = String::new();
-        let mut cells: Vec<String> = Vec::new();
-
-        let characters: Vec<char> = line.chars().collect();
-
-        let mut i = 0;
-
-        while i < characters.len() {
-            if characters[i] == '"' && !in_quotes {
-                in_quotes = true;
-                current_cell = String::new(); // Ignore anything that was there before the quotes.
-            } else if characters[i] == '"' && in_quotes {
-                if i + 1 >= characters.len() {
-                    in_quotes = false;
-                    cells.push(current_cell);
-                    current_cell = String::new();
-                } else {
-                    let p = characters[i + 1];
-
-                    if p != '\0' && p != '"' {
-                        in_quotes = false;
-                    } else if p == '"' {
-                        i += 1;
-                        current_cell.push(characters[i]);
-                    }
-                }
-            } else {
-                if characters[i] == ',' && !in_quotes {
-                    cells.push(current_cell);
-                    current_cell = String::new();
-                } else {
-                    current_cell.push(characters[i]);
-                }
-            }
+// Writes the lines of a document with the write options, shared by CSVDocument and the streaming
+// writers so both produce the same output.
+pub(crate) struct LineWriter<'a> {
+    options: &'a CSVWriteOptions,
+    numeric: Vec<bool>, // Whether each column is in the numeric column allowlist.
+}
 
-            i += 1;
-        }
+impl<'a> LineWriter<'a> {
+    pub(crate) fn new(header: &CSVRow, options: &'a CSVWriteOptions) -> LineWriter<'a> {
+        let numeric = header
+            .cells
+            .iter()
+            .map(|name| options.numeric_columns.contains(name))
+            .collect();
+
+        return LineWriter { options, numeric };
+    }
 
-        if in_quotes {
-            return Err("Unterminated quotes in cell.");
+    pub(crate) fn bom(&self) -> String {
+        if self.options.bom && self.options.encoding.has_bom() {
+            return UTF8_BOM.to_string();
         }
 
-        if current_cell.len() != 0 {
-            cells.push(current_cell);
+        return String::new();
+    }
+
+    // The header line, which is empty when the dialect or the document has no header. Header names
+    // are always sanitized, the numeric column allowlist only applies to data rows.
+    pub(crate) fn header(&self, header: &CSVRow) -> String {
+        let dialect = &self.options.dialect;
+
+        if !dialect.has_header || header.is_empty() {
+            return String::new();
         }
 
-        return Ok(CSVRow { cells });
+        return header.write(dialect, self.options.sanitize, &[])
+            + dialect.line_terminator.as_str();
+    }
+
+    pub(crate) fn row(&self, row: &CSVRow) -> String {
+        let dialect = &self.options.dialect;
+
+        return row.write(dialect, self.options.sanitize, &self.numeric)
+            + dialect.line_terminator.as_str();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::csv_dialect::CSVTrim;
 
     #[test]
     #[should_panic(expected = "Unterminated quotes in cell.")]
@@ -230,6 +340,7 @@ mod tests {
                 "none".to_string(),
                 "none".to_string(),
             ],
+            ..CSVRow::new()
         };
 
         assert_eq!(row.to_string(), "james,none,none,none");
@@ -244,6 +355,7 @@ mod tests {
                 "none".to_string(),
                 "none".to_string(),
             ],
+            ..CSVRow::new()
         };
 
         assert_eq!(row.to_string(), "\"\"\"james\"\"\",none,none,none");
@@ -258,6 +370,7 @@ mod tests {
                 "none".to_string(),
                 "none".to_string(),
             ],
+            ..CSVRow::new()
         };
 
         assert_eq!(
@@ -275,6 +388,7 @@ mod tests {
                 "none".to_string(),
                 "none".to_string(),
             ],
+            ..CSVRow::new()
         };
 
         assert_eq!(row.to_string(), "\"james\n\",none,none,none");
@@ -289,8 +403,240 @@ mod tests {
                 "none".to_string(),
                 "none".to_string(),
             ],
+            ..CSVRow::new()
         };
 
         assert_eq!(row.to_string(), "\"james,\",none,none,none");
     }
+
+    #[test]
+    fn test_parse_line_with_dialect() {
+        let mut dialect = CSVDialect::new();
+        dialect.delimiter = ';';
+        dialect.quote = '\'';
+
+        let line: String = "a;'b;''c''';d,e".to_string();
+        let row: CSVRow = CSVRow::parse_line_with_dialect(&line, &dialect).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["a", "b;'c'", "d,e"]);
+    }
+
+    #[test]
+    fn test_to_string_with_dialect() {
+        let mut dialect = CSVDialect::new();
+        dialect.delimiter = '|';
+        dialect.quote = '\'';
+
+        let row: CSVRow = CSVRow {
+            cells: vec![
+                "a|b".to_string(),
+                "it's".to_string(),
+                "c,d".to_string(),
+                "\"e\"".to_string(),
+            ],
+            ..CSVRow::new()
+        };
+
+        assert_eq!(
+            row.to_string_with_dialect(&dialect),
+            "'a|b'|'it''s'|c,d|\"e\""
+        );
+    }
+
+    #[test]
+    fn test_parse_line_trim_unquoted() {
+        let mut dialect = CSVDialect::new();
+        dialect.trim = CSVTrim::UnquotedFields;
+
+        let line: String = "a, b ,\t c,  \" d \" ,e".to_string();
+        let row: CSVRow = CSVRow::parse_line_with_dialect(&line, &dialect).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["a", "b", "c", " d ", "e"]);
+    }
+
+    #[test]
+    fn test_parse_line_trim_all() {
+        let mut dialect = CSVDialect::new();
+        dialect.trim = CSVTrim::AllFields;
+
+        let line: String = "a, b ,\" c \"".to_string();
+        let row: CSVRow = CSVRow::parse_line_with_dialect(&line, &dialect).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_parse_line_trim_none() {
+        let line: String = "a, b ,\" c \"".to_string();
+        let row: CSVRow = CSVRow::parse_line_new(&line).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["a", " b ", " c "]);
+    }
+
+    #[test]
+    fn test_parse_line_backslash_escapes() {
+        let mut dialect = CSVDialect::new();
+        dialect.escape = Some('\\');
+
+        let line: String = "a\\,b,\"say \\\"hi\\\"\",c\\nd\\te\\\\f\\r".to_string();
+        let row: CSVRow = CSVRow::parse_line_with_dialect(&line, &dialect).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["a,b", "say \"hi\"", "c\nd\te\\f\r"]);
+    }
+
+    #[test]
+    fn test_to_string_backslash_escapes() {
+        let mut dialect = CSVDialect::new();
+        dialect.escape = Some('\\');
+
+        let row: CSVRow = CSVRow {
+            cells: vec![
+                "a,b".to_string(),
+                "say \"hi\"".to_string(),
+                "c\nd\te\\f\r".to_string(),
+            ],
+            ..CSVRow::new()
+        };
+        let line = row.to_string_with_dialect(&dialect);
+
+        // The trailing carriage return is whitespace at the end of the cell, so it is also quoted.
+        assert_eq!(line, "a\\,b,\"say \\\"hi\\\"\",\"c\\nd\\te\\\\f\\r\"");
+        assert_eq!(
+            CSVRow::parse_line_with_dialect(&line, &dialect)
+                .unwrap()
+                .cells,
+            row.cells
+        );
+    }
+
+    #[test]
+    fn test_parse_line_trailing_empty() {
+        let line: String = "a,b,".to_string();
+        let row: CSVRow = CSVRow::parse_line_new(&line).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["a", "b", ""]);
+
+        let line: String = ",".to_string();
+        let row: CSVRow = CSVRow::parse_line_new(&line).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["", ""]);
+    }
+
+    #[test]
+    fn test_parse_line_quoted_empty() {
+        let line: String = "1,,\"\",\"x\"".to_string();
+        let row: CSVRow = CSVRow::parse_line_new(&line).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        assert_eq!(row.cells, vec!["1", "", "", "x"]);
+        assert_eq!(row.quoted, vec![false, false, true, true]);
+
+        let one = String::from("1");
+        let empty = String::new();
+        let x = String::from("x");
+        assert_eq!(
+            row.nullable_cells(),
+            vec![Some(&one), None, Some(&empty), Some(&x)]
+        );
+    }
+
+    #[test]
+    fn test_to_string_quoted_empty() {
+        let row: CSVRow = CSVRow {
+            cells: vec!["".to_string(), "".to_string(), "a".to_string()],
+            quoted: vec![false, true, true],
+        };
+
+        assert_eq!(row.to_string(), ",\"\",a");
+    }
+
+    #[test]
+    fn test_to_string_lone_empty_cell() {
+        let row: CSVRow = CSVRow {
+            cells: vec!["".to_string()],
+            ..CSVRow::new()
+        };
+
+        assert_eq!(row.to_string(), "\"\"");
+    }
+
+    #[test]
+    fn test_to_string_leading_comment_and_bom() {
+        let mut dialect = CSVDialect::new();
+        dialect.comment = Some('#');
+
+        let row: CSVRow = CSVRow {
+            cells: vec!["#1".to_string(), "#2".to_string(), "\u{feff}3".to_string()],
+            ..CSVRow::new()
+        };
+
+        assert_eq!(
+            row.to_string_with_dialect(&dialect),
+            "\"#1\",#2,\"\u{feff}3\""
+        );
+    }
+
+    #[test]
+    fn test_to_string_sanitize() {
+        let row: CSVRow = CSVRow {
+            cells: vec![
+                "=1+2".to_string(),
+                "+1".to_string(),
+                "-2+3+cmd|' /C calc'!A0".to_string(),
+                "@SUM(A1)".to_string(),
+                "\tx".to_string(),
+                "\ry".to_string(),
+                "a=b".to_string(),
+            ],
+            ..CSVRow::new()
+        };
+
+        let mut options = CSVWriteOptions::new();
+        assert_eq!(row.to_string_with_options(&options), row.to_string());
+
+        options.sanitize = CSVSanitize::Prefix;
+        assert_eq!(
+            row.to_string_with_options(&options),
+            "'=1+2,'+1,\"'-2+3+cmd|' /C calc'!A0\",'@SUM(A1),\"'\tx\",\"'\ry\",a=b"
+        );
+
+        options.sanitize = CSVSanitize::PrefixAndQuote;
+        assert_eq!(
+            row.to_string_with_options(&options),
+            "\"'=1+2\",\"'+1\",\"'-2+3+cmd|' /C calc'!A0\",\"'@SUM(A1)\",\"'\tx\",\"'\ry\",a=b"
+        );
+    }
+
+    #[test]
+    fn test_eq_quoted() {
+        let row: CSVRow = CSVRow {
+            cells: vec!["a".to_string(), "".to_string()],
+            ..CSVRow::new()
+        };
+
+        assert_eq!(row, CSVRow::parse_line_new(&"a,".to_string()).unwrap());
+        assert_ne!(row, CSVRow::parse_line_new(&"a,\"\"".to_string()).unwrap());
+        assert_ne!(row, CSVRow::parse_line_new(&"a,b".to_string()).unwrap());
+
+        let quoted: CSVRow = CSVRow {
+            quoted: vec![false, false, false],
+            ..row.clone()
+        };
+        assert_eq!(row, quoted);
+    }
 }
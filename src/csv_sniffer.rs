@@ -0,0 +1,279 @@
+use crate::csv_dialect::CSVDialect;
+use crate::csv_dialect::CSVLineTerminator;
+use crate::csv_options::UTF8_BOM;
+use crate::csv_row::CSVRow;
+use crate::csv_tokenizer::CSVToken;
+use crate::csv_tokenizer::CSVTokenizer;
+
+const DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+const QUOTES: [char; 2] = ['"', '\''];
+
+// The best guess at the dialect of a sample and how sure the guess is, from 0.0 to 1.0.
+#[derive(Clone, Debug)]
+pub struct CSVSniffResult {
+    pub dialect: CSVDialect,
+    pub confidence: f64,
+}
+
+// Examines a sample of CSV and guesses its dialect. The delimiter is the candidate that splits the
+// records into the most consistent number of cells, and the confidence is that consistency scaled
+// down for small samples. A confidence of zero means no delimiter could be found.
+pub fn sniff(sample: &str) -> CSVSniffResult {
+    let mut characters: Vec<char> = sample.chars().collect();
+
+    if characters.first() == Some(&UTF8_BOM) {
+        characters.remove(0);
+    }
+
+    let mut dialect = CSVDialect::new();
+    dialect.line_terminator = detect_line_terminator(&characters);
+    dialect.quote = detect_quote(&characters);
+
+    let mut best_rows: Vec<CSVRow> = vec![];
+    let mut best_score = (0.0, 0);
+
+    for delimiter in DELIMITERS.iter() {
+        let mut candidate = dialect.clone();
+        candidate.delimiter = *delimiter;
+
+        let rows = sample_rows(&characters, &candidate);
+        let (consistency, width) = width_consistency(&rows);
+
+        if width > 1 && (consistency, width) > best_score {
+            best_score = (consistency, width);
+            best_rows = rows;
+            dialect.delimiter = *delimiter;
+        }
+    }
+
+    let (consistency, _) = best_score;
+    dialect.has_header = detect_header(&best_rows);
+
+    // A single record is only weak evidence, each extra record makes the guess more believable.
+    let confidence = consistency * (best_rows.len() as f64 / (best_rows.len() as f64 + 1.0));
+
+    return CSVSniffResult {
+        dialect,
+        confidence,
+    };
+}
+
+// Picks the most common line terminator. Line breaks inside quoted cells are counted too, they are
+// rare compared to the breaks between records.
+fn detect_line_terminator(characters: &[char]) -> CSVLineTerminator {
+    let mut lf = 0;
+    let mut crlf = 0;
+    let mut cr = 0;
+    let mut i = 0;
+
+    while i < characters.len() {
+        if characters[i] == '\r' && characters.get(i + 1) == Some(&'\n') {
+            crlf += 1;
+            i += 1;
+        } else if characters[i] == '\r' {
+            cr += 1;
+        } else if characters[i] == '\n' {
+            lf += 1;
+        }
+
+        i += 1;
+    }
+
+    if crlf >= lf && crlf >= cr && crlf > 0 {
+        return CSVLineTerminator::CRLF;
+    } else if cr > lf {
+        return CSVLineTerminator::CR;
+    }
+
+    return CSVLineTerminator::LF;
+}
+
+// A quote character opens a cell right after a delimiter or line break and closes it right before
+// one. The candidate seen in both positions most often wins, with the double quote as the default.
+fn detect_quote(characters: &[char]) -> char {
+    let is_boundary = |c: Option<&char>| match c {
+        None => true,
+        Some(c) => *c == '\n' || *c == '\r' || DELIMITERS.contains(c),
+    };
+
+    let mut best = (0, QUOTES[0]);
+
+    for quote in QUOTES.iter() {
+        let mut opening = 0;
+        let mut closing = 0;
+
+        for i in 0..characters.len() {
+            if characters[i] != *quote {
+                continue;
+            }
+
+            if i == 0 || is_boundary(characters.get(i - 1)) {
+                opening += 1;
+            }
+
+            if is_boundary(characters.get(i + 1)) {
+                closing += 1;
+            }
+        }
+
+        let score = opening.min(closing);
+
+        if score > best.0 {
+            best = (score, *quote);
+        }
+    }
+
+    return best.1;
+}
+
+// Parses the complete records of the sample, a record cut off by the end of the sample is dropped.
+fn sample_rows(characters: &[char], dialect: &CSVDialect) -> Vec<CSVRow> {
+    let mut tokenizer = CSVTokenizer::new(dialect);
+    let mut rows: Vec<CSVRow> = vec![];
+
+    for c in characters {
+        if let Ok(Some(CSVToken::Record(row))) = tokenizer.push(*c) {
+            rows.push(row);
+        }
+    }
+
+    // The final record is only kept when it is the whole sample.
+    if rows.is_empty() {
+        if let Ok(Some(CSVToken::Record(row))) = tokenizer.finish() {
+            rows.push(row);
+        }
+    }
+
+    return rows;
+}
+
+// Returns the fraction of rows that have the most common width, along with that width.
+fn width_consistency(rows: &[CSVRow]) -> (f64, usize) {
+    let mut counts: Vec<(usize, usize)> = vec![];
+
+    for row in rows {
+        match counts.iter_mut().find(|(width, _)| *width == row.len()) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((row.len(), 1)),
+        }
+    }
+
+    let mut best = (0, 0);
+
+    for (width, count) in counts {
+        if count > best.1 || (count == best.1 && width > best.0) {
+            best = (width, count);
+        }
+    }
+
+    if rows.is_empty() {
+        return (0.0, 0);
+    }
+
+    return (best.1 as f64 / rows.len() as f64, best.0);
+}
+
+// Each column votes on whether the first row looks different from the rows below it, either by being
+// text above a column of numbers or by breaking a column of fixed length values.
+fn detect_header(rows: &[CSVRow]) -> bool {
+    if rows.len() < 2 {
+        return true;
+    }
+
+    let is_number = |s: &String| s.trim().parse::<f64>().is_ok();
+    let first = &rows[0];
+    let mut votes: i64 = 0;
+
+    for column in 0..first.len() {
+        let values: Vec<&String> = rows[1..]
+            .iter()
+            .filter(|row| row.len() == first.len())
+            .map(|row| &row.cells[column])
+            .collect();
+
+        if values.is_empty() {
+            continue;
+        }
+
+        let candidate = &first.cells[column];
+
+        if values.iter().all(|v| is_number(v)) {
+            votes += if is_number(candidate) { -1 } else { 1 };
+        } else if values
+            .iter()
+            .all(|v| v.chars().count() == values[0].chars().count())
+        {
+            votes += if candidate.chars().count() == values[0].chars().count() {
+                -1
+            } else {
+                1
+            };
+        }
+    }
+
+    return votes > 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_document::CSVDocument;
+    use crate::csv_options::CSVParseOptions;
+
+    #[test]
+    fn test_sniff_semicolon() {
+        let result = sniff("name;age\nbob;30\nann;41\n");
+
+        assert_eq!(result.dialect.delimiter, ';');
+        assert_eq!(result.dialect.quote, '"');
+        assert_eq!(result.dialect.line_terminator, CSVLineTerminator::LF);
+        assert!(result.dialect.has_header);
+        assert!(result.confidence > 0.7);
+    }
+
+    #[test]
+    fn test_sniff_tab_crlf_no_header() {
+        let result = sniff("1\t2\t3\r\n4\t5\t6\r\n7\t8\t9\r\n");
+
+        assert_eq!(result.dialect.delimiter, '\t');
+        assert_eq!(result.dialect.line_terminator, CSVLineTerminator::CRLF);
+        assert!(!result.dialect.has_header);
+    }
+
+    #[test]
+    fn test_sniff_single_quotes() {
+        let result = sniff("id|note\n1|'a|b'\n2|'c'\n3|'d, e'\n");
+
+        assert_eq!(result.dialect.delimiter, '|');
+        assert_eq!(result.dialect.quote, '\'');
+        assert!(result.dialect.has_header);
+    }
+
+    #[test]
+    fn test_sniff_truncated_sample() {
+        let result = sniff("a,b,c\n1,2,3\n4,5,6\n7,\"eig");
+
+        assert_eq!(result.dialect.delimiter, ',');
+        assert!(result.confidence > 0.7);
+    }
+
+    #[test]
+    fn test_sniff_no_delimiter() {
+        let result = sniff("hello\nworld\n");
+
+        assert_eq!(result.confidence, 0.0);
+    }
+
+    #[test]
+    fn test_sniff_into_parser() {
+        let input: String = "name;age\r\nbob;30\r\nann;41\r\n".to_string();
+        let options = CSVParseOptions::from_dialect(sniff(&input).dialect);
+        let doc: CSVDocument = CSVDocument::parse_string_with_options(&input, &options)
+            .unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.header.cells, vec!["name", "age"]);
+        assert_eq!(doc.contents[1].cells, vec!["ann", "41"]);
+    }
+}
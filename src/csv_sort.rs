@@ -0,0 +1,388 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::cmp::Ordering;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVSortDirection {
+    Ascending,
+    Descending,
+}
+
+// How the cells of a sort key are compared. Cells that the numeric and date modes cannot parse sort
+// after the ones they can, in lexicographic order, whatever the direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CSVSortMode {
+    Lexicographic,
+    Numeric,
+    Natural, // Runs of digits compare by value, so file2 sorts before file10.
+    CaseInsensitive,
+    Date, // ISO 8601 dates with an optional time, or day first dates such as 14/03/2000.
+}
+
+// A column to sort by. Empty cells are placed last, or first when empty_first is set, whatever the
+// direction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVSortKey {
+    pub column: String,
+    pub direction: CSVSortDirection,
+    pub mode: CSVSortMode,
+    pub empty_first: bool,
+}
+
+impl CSVSortKey {
+    pub fn new(column: &str, direction: CSVSortDirection, mode: CSVSortMode) -> CSVSortKey {
+        return CSVSortKey {
+            column: column.to_string(),
+            direction,
+            mode,
+            empty_first: false,
+        };
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        let empty = if self.empty_first {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+
+        return match (a.is_empty(), b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => empty,
+            (false, true) => empty.reverse(),
+            (false, false) => match self.mode {
+                CSVSortMode::Lexicographic => self.directed(a.cmp(b)),
+                CSVSortMode::Numeric => self.compare_parsed(a, b, parse_number),
+                CSVSortMode::Natural => self.directed(compare_natural(a, b)),
+                CSVSortMode::CaseInsensitive => {
+                    self.directed(a.to_lowercase().cmp(&b.to_lowercase()))
+                }
+                CSVSortMode::Date => self.compare_parsed(a, b, parse_date),
+            },
+        };
+    }
+
+    fn directed(&self, ordering: Ordering) -> Ordering {
+        return match self.direction {
+            CSVSortDirection::Ascending => ordering,
+            CSVSortDirection::Descending => ordering.reverse(),
+        };
+    }
+
+    // Only the parsed values follow the direction, cells that cannot be parsed stay last and in
+    // lexicographic order like empty cells do.
+    fn compare_parsed<T: PartialOrd, F: Fn(&str) -> Option<T>>(
+        &self,
+        a: &str,
+        b: &str,
+        parse: F,
+    ) -> Ordering {
+        return match (parse(a), parse(b)) {
+            (Some(x), Some(y)) => self.directed(x.partial_cmp(&y).unwrap_or(Ordering::Equal)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.cmp(b),
+        };
+    }
+}
+
+impl CSVDocument {
+    // Sorts the rows by the keys, the first key deciding first. The sort is stable, so rows that
+    // compare equal keep their order.
+    pub fn sort_by_columns(&mut self, keys: &[CSVSortKey]) -> Result<(), &'static str> {
+        if let Err(e) = self.check_columns() {
+            return Err(e);
+        }
+
+        let resolved = match resolve_keys(&self.header, keys) {
+            Ok(resolved) => resolved,
+            Err(e) => return Err(e),
+        };
+
+        self.contents.sort_by(|a, b| compare_rows(a, b, &resolved));
+
+        return Ok(());
+    }
+}
+
+// Pairs each key with the index of its column in the header.
+pub(crate) fn resolve_keys<'a>(
+    header: &CSVRow,
+    keys: &'a [CSVSortKey],
+) -> Result<Vec<(usize, &'a CSVSortKey)>, &'static str> {
+    let mut resolved = vec![];
+
+    for key in keys {
+        match header.cells.iter().position(|cell| *cell == key.column) {
+            Some(index) => resolved.push((index, key)),
+            None => return Err("The column does not exist."),
+        }
+    }
+
+    return Ok(resolved);
+}
+
+pub(crate) fn compare_rows(a: &CSVRow, b: &CSVRow, keys: &[(usize, &CSVSortKey)]) -> Ordering {
+    for (index, key) in keys {
+        let ordering = key.compare(&a.cells[*index], &b.cells[*index]);
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    return Ordering::Equal;
+}
+
+// NaN has no place in the order and would break the sort, so non-finite values count as text.
+fn parse_number(s: &str) -> Option<f64> {
+    return s.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+}
+
+// Compares runs of digits by their value and everything else character by character.
+fn compare_natural(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_digits(&mut a);
+                let y = take_digits(&mut b);
+
+                // With the leading zeros gone a longer run is a larger number.
+                let ordering = x
+                    .trim_start_matches('0')
+                    .len()
+                    .cmp(&y.trim_start_matches('0').len())
+                    .then_with(|| x.trim_start_matches('0').cmp(y.trim_start_matches('0')))
+                    .then_with(|| x.len().cmp(&y.len()));
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(y);
+
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_digits(characters: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+
+    while let Some(c) = characters.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+
+    return digits;
+}
+
+// Parses YYYY-MM-DD with an optional time such as T10:30:00 or 10:30, or DD/MM/YYYY, into a value
+// that sorts chronologically. A trailing Z is ignored, other time zone offsets are not supported.
+fn parse_date(s: &str) -> Option<(i64, u32, u32, u32, u32, f64)> {
+    let s = s.trim();
+    let s = s.strip_suffix('Z').unwrap_or(s);
+
+    let (date, time) = match s.find(['T', ' ']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+
+    let (year, month, day) = if date.contains('-') {
+        let parts: Vec<&str> = date.split('-').collect();
+
+        if parts.len() != 3 || parts[0].len() != 4 {
+            return None;
+        }
+
+        (parts[0], parts[1], parts[2])
+    } else {
+        let parts: Vec<&str> = date.split('/').collect();
+
+        if parts.len() != 3 || parts[2].len() != 4 {
+            return None;
+        }
+
+        (parts[2], parts[1], parts[0])
+    };
+
+    let (year, month, day) = match (
+        year.parse::<i64>(),
+        month.parse::<u32>(),
+        day.parse::<u32>(),
+    ) {
+        (Ok(year), Ok(month), Ok(day)) => (year, month, day),
+        _ => return None,
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (mut hour, mut minute, mut second) = (0, 0, 0.0);
+
+    if let Some(time) = time {
+        let parts: Vec<&str> = time.split(':').collect();
+
+        if parts.len() < 2 || parts.len() > 3 {
+            return None;
+        }
+
+        hour = match parts[0].parse::<u32>() {
+            Ok(hour) => hour,
+            Err(_) => return None,
+        };
+
+        minute = match parts[1].parse::<u32>() {
+            Ok(minute) => minute,
+            Err(_) => return None,
+        };
+
+        if parts.len() == 3 {
+            second = match parts[2].parse::<f64>() {
+                Ok(second) => second,
+                Err(_) => return None,
+            };
+        }
+
+        if hour > 23 || minute > 59 || !(0.0..61.0).contains(&second) {
+            return None;
+        }
+    }
+
+    return Some((year, month, day, hour, minute, second));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASC: CSVSortDirection = CSVSortDirection::Ascending;
+    const DESC: CSVSortDirection = CSVSortDirection::Descending;
+
+    fn column(doc: &CSVDocument, index: usize) -> Vec<&str> {
+        return doc
+            .contents
+            .iter()
+            .map(|row| row.cells[index].as_str())
+            .collect();
+    }
+
+    #[test]
+    fn test_sort_by_columns() {
+        let string: String =
+            "name,score\nbob,10\nann,9\n,3\ncat,10\ndan,\neve,abc\nfay,9\n".to_string();
+        let mut doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        doc.sort_by_columns(&[CSVSortKey::new("score", ASC, CSVSortMode::Numeric)])
+            .unwrap();
+        assert_eq!(
+            column(&doc, 0),
+            vec!["", "ann", "fay", "bob", "cat", "eve", "dan"]
+        );
+
+        doc.sort_by_columns(&[
+            CSVSortKey::new("score", DESC, CSVSortMode::Numeric),
+            CSVSortKey::new("name", DESC, CSVSortMode::Lexicographic),
+        ])
+        .unwrap();
+        assert_eq!(
+            column(&doc, 0),
+            vec!["cat", "bob", "fay", "ann", "", "eve", "dan"]
+        );
+
+        let mut key = CSVSortKey::new("name", ASC, CSVSortMode::Lexicographic);
+        key.empty_first = true;
+        doc.sort_by_columns(&[key]).unwrap();
+        assert_eq!(
+            column(&doc, 0),
+            vec!["", "ann", "bob", "cat", "dan", "eve", "fay"]
+        );
+
+        assert_eq!(
+            doc.sort_by_columns(&[CSVSortKey::new("age", ASC, CSVSortMode::Lexicographic)]),
+            Err("The column does not exist.")
+        );
+    }
+
+    #[test]
+    fn test_sort_not_a_number() {
+        let string: String = "n\n3\nNaN\n1\ninf\n-2\nnan\n2\n".to_string();
+        let mut doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        doc.sort_by_columns(&[CSVSortKey::new("n", ASC, CSVSortMode::Numeric)])
+            .unwrap();
+        assert_eq!(
+            column(&doc, 0),
+            vec!["-2", "1", "2", "3", "NaN", "inf", "nan"]
+        );
+    }
+
+    #[test]
+    fn test_sort_stable() {
+        let string: String = "key,order\nB,1\na,2\nb,3\nA,4\n".to_string();
+        let mut doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        doc.sort_by_columns(&[CSVSortKey::new("key", ASC, CSVSortMode::CaseInsensitive)])
+            .unwrap();
+        assert_eq!(column(&doc, 1), vec!["2", "4", "1", "3"]);
+
+        doc.sort_by_columns(&[CSVSortKey::new("key", ASC, CSVSortMode::Lexicographic)])
+            .unwrap();
+        assert_eq!(column(&doc, 0), vec!["A", "B", "a", "b"]);
+    }
+
+    #[test]
+    fn test_compare_natural() {
+        let mut values = vec![
+            "file10", "file2", "file02", "File1", "file1b", "file", "10", "9",
+        ];
+        values.sort_by(|a, b| compare_natural(a, b));
+
+        assert_eq!(
+            values,
+            vec!["9", "10", "File1", "file", "file1b", "file2", "file02", "file10"]
+        );
+    }
+
+    #[test]
+    fn test_sort_dates() {
+        let string: String =
+            "when\n2021-03-01\n14/03/2000\n2021-03-01T09:15:00Z\nsoon\n2020-12-31 23:59\n"
+                .to_string();
+        let mut doc: CSVDocument = CSVDocument::parse_string(&string).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+
+        doc.sort_by_columns(&[CSVSortKey::new("when", ASC, CSVSortMode::Date)])
+            .unwrap();
+        assert_eq!(
+            column(&doc, 0),
+            vec![
+                "14/03/2000",
+                "2020-12-31 23:59",
+                "2021-03-01",
+                "2021-03-01T09:15:00Z",
+                "soon"
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use crate::csv_document::CSVDocument;
+use crate::csv_row::CSVRow;
+use std::collections::HashMap;
+
+const DEFAULT_TOP_VALUES: usize = 5;
+
+// Descriptive statistics for one column. Everything except count and empty_count is computed over
+// the non-empty cells. The numeric statistics are only filled in when every non-empty cell parses
+// as a number, and min and max then compare numerically instead of as text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CSVColumnStats {
+    pub name: String,
+    pub count: usize,
+    pub empty_count: usize,
+    pub distinct_count: usize,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    pub std_dev: Option<f64>, // The sample standard deviation, it needs at least two values.
+    pub top_values: Vec<(String, usize)>, // The most frequent values, most frequent first.
+    pub min_length: Option<usize>, // Lengths are counted in characters.
+    pub max_length: Option<usize>,
+}
+
+// The running state of one column. The mean and variance use Welford's method and the median is
+// taken from the value counts, so memory grows with the number of distinct values, not rows.
+struct ColumnAccumulator {
+    count: usize,
+    empty_count: usize,
+    values: HashMap<String, usize>,
+    numeric: bool,
+    numbers: usize,
+    mean: f64,
+    m2: f64,
+    min_number: Option<(f64, String)>,
+    max_number: Option<(f64, String)>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+}
+
+impl ColumnAccumulator {
+    fn new() -> ColumnAccumulator {
+        return ColumnAccumulator {
+            count: 0,
+            empty_count: 0,
+            values: HashMap::new(),
+            numeric: true,
+            numbers: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min_number: None,
+            max_number: None,
+            min_length: None,
+            max_length: None,
+        };
+    }
+
+    fn add(&mut self, cell: &str) {
+        self.count += 1;
+
+        if cell.is_empty() {
+            self.empty_count += 1;
+            return;
+        }
+
+        *self.values.entry(cell.to_string()).or_insert(0) += 1;
+
+        let length = cell.chars().count();
+        self.min_length = Some(self.min_length.map_or(length, |l| l.min(length)));
+        self.max_length = Some(self.max_length.map_or(length, |l| l.max(length)));
+
+        if !self.numeric {
+            return;
+        }
+
+        let number = match cell.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => number,
+            _ => {
+                self.numeric = false;
+                return;
+            }
+        };
+
+        self.numbers += 1;
+        let delta = number - self.mean;
+        self.mean += delta / self.numbers as f64;
+        self.m2 += delta * (number - self.mean);
+
+        if self
+            .min_number
+            .as_ref()
+            .is_none_or(|(min, _)| number < *min)
+        {
+            self.min_number = Some((number, cell.to_string()));
+        }
+
+        if self
+            .max_number
+            .as_ref()
+            .is_none_or(|(max, _)| number > *max)
+        {
+            self.max_number = Some((number, cell.to_string()));
+        }
+    }
+
+    fn finish(&self, name: &str, top: usize) -> CSVColumnStats {
+        let mut top_values: Vec<(String, usize)> = self
+            .values
+            .iter()
+            .map(|(value, count)| (value.clone(), *count))
+            .collect();
+
+        // Ties are broken by the value so the report does not depend on hashing order.
+        top_values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_values.truncate(top);
+
+        let mut stats = CSVColumnStats {
+            name: name.to_string(),
+            count: self.count,
+            empty_count: self.empty_count,
+            distinct_count: self.values.len(),
+            min: self.values.keys().min().cloned(),
+            max: self.values.keys().max().cloned(),
+            mean: None,
+            median: None,
+            std_dev: None,
+            top_values,
+            min_length: self.min_length,
+            max_length: self.max_length,
+        };
+
+        if self.numeric && self.numbers > 0 {
+            stats.min = self.min_number.as_ref().map(|(_, cell)| cell.to_string());
+            stats.max = self.max_number.as_ref().map(|(_, cell)| cell.to_string());
+            stats.mean = Some(self.mean);
+            stats.median = Some(self.median());
+
+            if self.numbers > 1 {
+                stats.std_dev = Some((self.m2 / (self.numbers - 1) as f64).sqrt());
+            }
+        }
+
+        return stats;
+    }
+
+    fn median(&self) -> f64 {
+        let mut numbers: Vec<(f64, usize)> = self
+            .values
+            .iter()
+            .map(|(value, count)| (value.trim().parse::<f64>().unwrap(), *count))
+            .collect();
+        numbers.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        // The values at the two middle positions, which are the same one for an odd count.
+        let positions = [(self.numbers - 1) / 2, self.numbers / 2];
+        let mut middle = [0.0; 2];
+        let mut seen = 0;
+
+        for (number, count) in numbers {
+            for i in 0..2 {
+                if positions[i] >= seen && positions[i] < seen + count {
+                    middle[i] = number;
+                }
+            }
+
+            seen += count;
+        }
+
+        return (middle[0] + middle[1]) / 2.0;
+    }
+}
+
+// Builds per column statistics one row at a time, e.g. over the rows of a CSVReader.
+pub struct CSVDescriber {
+    header: CSVRow,
+    top_values: usize,
+    columns: Vec<ColumnAccumulator>,
+}
+
+impl CSVDescriber {
+    // Starts statistics for the columns of the header, keeping the given number of most frequent
+    // values for each.
+    pub fn new(header: &CSVRow, top_values: usize) -> CSVDescriber {
+        return CSVDescriber {
+            header: header.clone(),
+            top_values,
+            columns: header
+                .cells
+                .iter()
+                .map(|_| ColumnAccumulator::new())
+                .collect(),
+        };
+    }
+
+    // Adds a row, cells beyond the header are ignored.
+    pub fn add_row(&mut self, row: &CSVRow) {
+        for (column, cell) in self.columns.iter_mut().zip(row.cells.iter()) {
+            column.add(cell);
+        }
+    }
+
+    pub fn finish(&self) -> Vec<CSVColumnStats> {
+        return self
+            .columns
+            .iter()
+            .zip(self.header.cells.iter())
+            .map(|(column, name)| column.finish(name, self.top_values))
+            .collect();
+    }
+}
+
+impl CSVDocument {
+    // Returns statistics for every column of the header, with the five most frequent values.
+    pub fn describe(&self) -> Vec<CSVColumnStats> {
+        let mut describer = CSVDescriber::new(&self.header, DEFAULT_TOP_VALUES);
+
+        for row in self.contents.iter() {
+            describer.add_row(row);
+        }
+
+        return describer.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_options::CSVParseOptions;
+    use crate::csv_reader::CSVReader;
+
+    const INPUT: &str = "name,score,city\nann,4,Auckland\nbob,,Wellington\ncat,8,Auckland\ndan,2,\neve,6,Auckland\nann,10,Nelson\n";
+
+    #[test]
+    fn test_describe() {
+        let doc = CSVDocument::parse_string(&INPUT.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+        let stats = doc.describe();
+
+        assert_eq!(stats.len(), 3);
+
+        let score = &stats[1];
+        assert_eq!(score.name, "score");
+        assert_eq!(score.count, 6);
+        assert_eq!(score.empty_count, 1);
+        assert_eq!(score.distinct_count, 5);
+        assert_eq!(score.min, Some("2".to_string()));
+        assert_eq!(score.max, Some("10".to_string()));
+        assert_eq!(score.mean, Some(6.0));
+        assert_eq!(score.median, Some(6.0));
+        assert!((score.std_dev.unwrap() - 10f64.sqrt()).abs() < 1e-9);
+        assert_eq!(score.min_length, Some(1));
+        assert_eq!(score.max_length, Some(2));
+
+        let city = &stats[2];
+        assert_eq!(city.min, Some("Auckland".to_string()));
+        assert_eq!(city.max, Some("Wellington".to_string()));
+        assert_eq!(city.mean, None);
+        assert_eq!(city.std_dev, None);
+        assert_eq!(
+            city.top_values,
+            vec![
+                ("Auckland".to_string(), 3),
+                ("Nelson".to_string(), 1),
+                ("Wellington".to_string(), 1)
+            ]
+        );
+        assert_eq!(city.min_length, Some(6));
+        assert_eq!(city.max_length, Some(10));
+
+        assert_eq!(stats[0].distinct_count, 5);
+        assert_eq!(stats[0].top_values[0], ("ann".to_string(), 2));
+    }
+
+    #[test]
+    fn test_describe_median_even() {
+        let doc =
+            CSVDocument::parse_string(&"n\n1\n3\n3\n10\n".to_string()).unwrap_or_else(|err| {
+                panic!("{}", err);
+            });
+
+        assert_eq!(doc.describe()[0].median, Some(3.0));
+        assert_eq!(doc.describe()[0].mean, Some(4.25));
+    }
+
+    #[test]
+    fn test_describe_streaming() {
+        let options = CSVParseOptions::new();
+        let mut reader = CSVReader::new(INPUT.as_bytes(), &options).unwrap();
+        let mut describer = CSVDescriber::new(reader.header(), 2);
+
+        while let Some(row) = reader.next_row().unwrap() {
+            describer.add_row(&row);
+        }
+
+        let doc = CSVDocument::parse_string(&INPUT.to_string()).unwrap_or_else(|err| {
+            panic!("{}", err);
+        });
+        let mut expected = doc.describe();
+
+        for column in expected.iter_mut() {
+            column.top_values.truncate(2);
+        }
+
+        assert_eq!(describer.finish(), expected);
+    }
+}
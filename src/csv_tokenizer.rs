@@ -0,0 +1,559 @@
+use crate::csv_dialect::unescape;
+use crate::csv_dialect::CSVDialect;
+use crate::csv_dialect::CSVLineTerminator;
+use crate::csv_dialect::CSVTrim;
+use crate::csv_options::CSVLimits;
+use crate::csv_row::CSVRow;
+
+// What the tokenizer produces for each line of input.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum CSVToken {
+    Record(CSVRow),
+    Comment(String), // The comment without its prefix character.
+    EmptyLine,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    StartOfRecord,
+    InField,
+    InQuotes,
+    QuoteInQuotes, // A quote was seen inside quotes, it is either doubled or closes the quotes.
+    Escaped(bool), // The escape character was seen, the flag records whether it was inside quotes.
+    InComment,
+}
+
+// Turns characters into records one character at a time, so it works the same on a whole string or
+// on a stream. Every parse entry point goes through this tokenizer.
+pub(crate) struct CSVTokenizer {
+    dialect: CSVDialect,
+    limits: CSVLimits,
+    single_record: bool,  // Treat line terminators as ordinary characters.
+    header_pending: bool, // The next record is the header.
+    state: State,
+    pending_cr: bool, // A carriage return that may be the start of a CRLF terminator.
+    row: CSVRow,
+    cell: String,
+    closed_at: Option<usize>, // Where the closing quote of the current cell was.
+    escaped: Option<(usize, usize)>, // Where the first escaped character starts and the last ends.
+    record_size: usize,       // The bytes of input consumed by the current line.
+}
+
+impl CSVTokenizer {
+    pub(crate) fn new(dialect: &CSVDialect) -> CSVTokenizer {
+        return CSVTokenizer::with_limits(dialect, &CSVLimits::new());
+    }
+
+    pub(crate) fn with_limits(dialect: &CSVDialect, limits: &CSVLimits) -> CSVTokenizer {
+        let mut dialect = dialect.clone();
+
+        // Escaping with the quote character is the same as doubling quotes, as Postgres writes it.
+        if dialect.escape == Some(dialect.quote) {
+            dialect.escape = None;
+        }
+
+        let has_header = dialect.has_header;
+
+        return CSVTokenizer {
+            dialect,
+            limits: limits.clone(),
+            single_record: false,
+            header_pending: has_header,
+            state: State::StartOfRecord,
+            pending_cr: false,
+            row: CSVRow::new(),
+            cell: String::new(),
+            closed_at: None,
+            escaped: None,
+            record_size: 0,
+        };
+    }
+
+    // A tokenizer for input that is already a single record, such as a line passed to CSVRow.
+    pub(crate) fn for_line(dialect: &CSVDialect) -> CSVTokenizer {
+        let mut tokenizer = CSVTokenizer::new(dialect);
+        tokenizer.single_record = true;
+        tokenizer.header_pending = false;
+        return tokenizer;
+    }
+
+    // Tokenizes a whole line, an empty line has no cells.
+    pub(crate) fn parse_line(line: &str, dialect: &CSVDialect) -> Result<CSVRow, &'static str> {
+        let mut tokenizer = CSVTokenizer::for_line(dialect);
+
+        for c in line.chars() {
+            if let Err(e) = tokenizer.push(c) {
+                return Err(e);
+            }
+        }
+
+        return match tokenizer.finish() {
+            Ok(Some(CSVToken::Record(row))) => Ok(row),
+            Ok(_) => Ok(CSVRow::new()),
+            Err(e) => Err(e),
+        };
+    }
+
+    // Feeds the next character, returning a token once a line has been completed. Limits are checked
+    // as the input arrives so oversized input fails before it has all been buffered.
+    pub(crate) fn push(&mut self, c: char) -> Result<Option<CSVToken>, &'static str> {
+        self.record_size += c.len_utf8();
+
+        if CSVLimits::exceeds(self.limits.max_record_size, self.record_size) {
+            return Err(CSVLimits::RECORD_SIZE_EXCEEDED);
+        }
+
+        let token = if self.pending_cr && c == '\n' {
+            self.pending_cr = false;
+            Some(self.end_line())
+        } else {
+            if self.pending_cr {
+                self.pending_cr = false;
+                self.push_lone_cr();
+            }
+
+            self.push_content(c)
+        };
+
+        if CSVLimits::exceeds(self.limits.max_field_size, self.cell.len()) {
+            return Err(CSVLimits::FIELD_SIZE_EXCEEDED);
+        }
+
+        if CSVLimits::exceeds(self.limits.max_columns, self.row.len()) {
+            return Err(CSVLimits::COLUMNS_EXCEEDED);
+        }
+
+        if let Some(CSVToken::Record(row)) = &token {
+            if CSVLimits::exceeds(self.limits.max_columns, row.len()) {
+                return Err(CSVLimits::COLUMNS_EXCEEDED);
+            }
+        }
+
+        return Ok(token);
+    }
+
+    fn push_content(&mut self, c: char) -> Option<CSVToken> {
+        if self.state == State::QuoteInQuotes {
+            if c == self.dialect.quote {
+                self.cell.push(c);
+                self.state = State::InQuotes;
+                return None;
+            }
+
+            self.closed_at = Some(self.cell.len());
+            self.state = State::InField;
+        }
+
+        match self.state {
+            State::Escaped(in_quotes) => {
+                let start = self.cell.len();
+                self.cell.push(unescape(c));
+                self.escaped = Some((self.escaped.map_or(start, |e| e.0), self.cell.len()));
+                self.state = if in_quotes {
+                    State::InQuotes
+                } else {
+                    State::InField
+                };
+                return None;
+            }
+            State::InQuotes => {
+                if self.dialect.escape == Some(c) {
+                    self.state = State::Escaped(true);
+                } else if c == self.dialect.quote {
+                    self.state = State::QuoteInQuotes;
+                } else {
+                    self.cell.push(c);
+                }
+
+                return None;
+            }
+            _ => (),
+        }
+
+        if !self.single_record {
+            match self.dialect.line_terminator {
+                CSVLineTerminator::LF if c == '\n' => return Some(self.end_line()),
+                CSVLineTerminator::CR if c == '\r' => return Some(self.end_line()),
+                CSVLineTerminator::CRLF if c == '\r' => {
+                    self.pending_cr = true;
+                    return None;
+                }
+                _ => (),
+            }
+        }
+
+        if self.state == State::InComment {
+            self.cell.push(c);
+            return None;
+        }
+
+        if self.state == State::StartOfRecord {
+            self.state = State::InField;
+
+            if self.dialect.comment == Some(c) && !self.single_record {
+                self.state = State::InComment;
+                return None;
+            }
+        }
+
+        if self.dialect.escape == Some(c) {
+            self.state = State::Escaped(false);
+        } else if c == self.dialect.quote {
+            self.state = State::InQuotes;
+            self.closed_at = None;
+            self.escaped = None;
+            self.cell = String::new(); // Ignore anything that was there before the quotes.
+        } else if c == self.dialect.delimiter {
+            self.end_cell();
+        } else {
+            self.cell.push(c);
+        }
+
+        return None;
+    }
+
+    // Signals the end of the input, returning the final line if it was not terminated.
+    pub(crate) fn finish(&mut self) -> Result<Option<CSVToken>, &'static str> {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.push_lone_cr();
+        }
+
+        match self.state {
+            State::StartOfRecord => return Ok(None),
+            State::InQuotes | State::Escaped(true) => {
+                return Err("Unterminated quotes in cell.");
+            }
+            State::Escaped(false) => {
+                // A trailing escape character has nothing to escape so it is kept as it is.
+                if let Some(escape) = self.dialect.escape {
+                    self.cell.push(escape);
+                }
+            }
+            State::QuoteInQuotes => {
+                self.closed_at = Some(self.cell.len());
+            }
+            State::InField | State::InComment => (),
+        }
+
+        if CSVLimits::exceeds(self.limits.max_field_size, self.cell.len()) {
+            return Err(CSVLimits::FIELD_SIZE_EXCEEDED);
+        }
+
+        let token = self.end_line();
+
+        if let CSVToken::Record(row) = &token {
+            if CSVLimits::exceeds(self.limits.max_columns, row.len()) {
+                return Err(CSVLimits::COLUMNS_EXCEEDED);
+            }
+        }
+
+        return Ok(Some(token));
+    }
+
+    // A carriage return that turned out not to be part of a CRLF terminator is ordinary content.
+    fn push_lone_cr(&mut self) {
+        if self.state == State::StartOfRecord {
+            self.state = State::InField;
+        }
+
+        self.cell.push('\r');
+    }
+
+    fn end_cell(&mut self) {
+        let cell = std::mem::take(&mut self.cell);
+
+        // Only the unquoted cells of the header are trimmed when the dialect trims headers.
+        let trim = match self.dialect.trim {
+            CSVTrim::HeadersOnly if self.header_pending => CSVTrim::UnquotedFields,
+            CSVTrim::HeadersOnly => CSVTrim::None,
+            trim => trim,
+        };
+
+        // Quoted text and escaped characters are kept as they are when trimming unquoted fields.
+        let kept = match (self.closed_at, self.escaped) {
+            (Some(closed_at), escaped) => {
+                Some((0, escaped.map_or(closed_at, |e| e.1.max(closed_at))))
+            }
+            (None, escaped) => escaped,
+        };
+
+        self.row.cells.push(trim_cell(cell, kept, trim));
+        self.row.quoted.push(self.closed_at.is_some());
+        self.closed_at = None;
+        self.escaped = None;
+    }
+
+    fn end_line(&mut self) -> CSVToken {
+        let state = self.state;
+        self.state = State::StartOfRecord;
+        self.record_size = 0;
+
+        match state {
+            State::StartOfRecord => return CSVToken::EmptyLine,
+            State::InComment => return CSVToken::Comment(std::mem::take(&mut self.cell)),
+            _ => {
+                // Every delimiter is followed by a cell, even an empty one at the end of the line.
+                self.end_cell();
+                self.header_pending = false;
+                return CSVToken::Record(std::mem::replace(&mut self.row, CSVRow::new()));
+            }
+        }
+    }
+}
+
+// Trims whitespace from a parsed cell. Unless every field is being trimmed, only the text before and
+// after the kept range is trimmed, which covers the quoted text and any escaped characters.
+fn trim_cell(cell: String, kept: Option<(usize, usize)>, trim: CSVTrim) -> String {
+    match (trim, kept) {
+        (CSVTrim::None, _) | (CSVTrim::HeadersOnly, _) => return cell,
+        (CSVTrim::AllFields, _) | (CSVTrim::UnquotedFields, None) => {
+            return cell.trim().to_string();
+        }
+        (CSVTrim::UnquotedFields, Some((start, end))) => {
+            let end = end.min(cell.len());
+            let start = start.min(end);
+            return format!(
+                "{}{}{}",
+                cell[..start].trim_start(),
+                &cell[start..end],
+                cell[end..].trim_end()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_document::CSVDocument;
+    use crate::csv_options::CSVParseOptions;
+
+    // RFC 4180 examples and known edge cases as (line, cells, quoted). Every parse entry point must
+    // agree on all of them.
+    const LINE_CASES: [(&str, &[&str], &[bool]); 18] = [
+        (
+            "aaa,bbb,ccc",
+            &["aaa", "bbb", "ccc"],
+            &[false, false, false],
+        ),
+        (
+            "\"aaa\",\"bbb\",\"ccc\"",
+            &["aaa", "bbb", "ccc"],
+            &[true, true, true],
+        ),
+        (
+            "\"aaa\",\"b\r\nbb\",\"ccc\"",
+            &["aaa", "b\r\nbb", "ccc"],
+            &[true, true, true],
+        ),
+        (
+            "\"aaa\",\"b\"\"bb\",\"ccc\"",
+            &["aaa", "b\"bb", "ccc"],
+            &[true, true, true],
+        ),
+        ("a,b,", &["a", "b", ""], &[false, false, false]),
+        (",", &["", ""], &[false, false]),
+        (",,", &["", "", ""], &[false, false, false]),
+        ("\"\"", &[""], &[true]),
+        ("\"\",\"\"", &["", ""], &[true, true]),
+        ("a,\"\",", &["a", "", ""], &[false, true, false]),
+        ("\"a,b\",c", &["a,b", "c"], &[true, false]),
+        ("\"\"\"\",a", &["\"", "a"], &[true, false]),
+        ("\"\"\"\"\"\"", &["\"\""], &[true]),
+        ("a b , c", &["a b ", " c"], &[false, false]),
+        (
+            "caf\u{e9},\u{1f600}",
+            &["caf\u{e9}", "\u{1f600}"],
+            &[false, false],
+        ),
+        ("\"a\"b,c", &["ab", "c"], &[true, false]),
+        ("x\"a\",c", &["a", "c"], &[true, false]),
+        ("a\"\"b,c", &["b", "c"], &[true, false]),
+    ];
+
+    const ERROR_CASES: [&str; 4] = ["\"abc", "a,\"b", "\"a\"\"", "a,\"b\"\"\"\","];
+
+    // Lines read with a backslash escape and trimmed unquoted fields, as (line, cells, quoted).
+    const ESCAPE_CASES: [(&str, &[&str], &[bool]); 5] = [
+        (" a\\ , b ", &["a ", "b"], &[false, false]),
+        ("\\ a ,\\\t", &[" a", "\t"], &[false, false]),
+        (" x\\ y ", &["x y"], &[false]),
+        (" \" a \"\\  ,b", &[" a  ", "b"], &[true, false]),
+        ("a\\,b ,c", &["a,b", "c"], &[false, false]),
+    ];
+
+    fn assert_row(row: &CSVRow, cells: &[&str], quoted: &[bool], entry_point: &str) {
+        assert_eq!(row.cells, cells, "{}", entry_point);
+        assert_eq!(row.quoted, quoted, "{}", entry_point);
+    }
+
+    #[test]
+    fn test_conformance_lines() {
+        for (line, cells, quoted) in LINE_CASES.iter() {
+            let line = line.to_string();
+
+            let row = CSVRow::parse_line_new(&line).unwrap();
+            assert_row(&row, cells, quoted, "parse_line_new");
+
+            let row = CSVRow::parse_line_with_dialect(&line, &CSVDialect::new()).unwrap();
+            assert_row(&row, cells, quoted, "parse_line_with_dialect");
+
+            let mut row = CSVRow::new();
+            row.parse_line(&line).unwrap();
+            assert_row(&row, cells, quoted, "parse_line");
+
+            let doc = CSVDocument::parse_string(&line).unwrap();
+            assert_row(&doc.header, cells, quoted, "parse_string");
+
+            let mut options = CSVParseOptions::new();
+            options.dialect.has_header = false;
+            let doc = CSVDocument::parse_string_with_options(&line, &options).unwrap();
+            assert_row(&doc.contents[0], cells, quoted, "parse_string_with_options");
+
+            let doc = CSVDocument::parse_bytes(line.as_bytes(), &options).unwrap();
+            assert_row(&doc.contents[0], cells, quoted, "parse_bytes");
+        }
+    }
+
+    #[test]
+    fn test_conformance_escapes() {
+        let mut options = CSVParseOptions::new();
+        options.dialect.has_header = false;
+        options.dialect.escape = Some('\\');
+        options.dialect.trim = CSVTrim::UnquotedFields;
+
+        for (line, cells, quoted) in ESCAPE_CASES.iter() {
+            let row = CSVRow::parse_line_with_dialect(&line.to_string(), &options.dialect).unwrap();
+            assert_row(&row, cells, quoted, "parse_line_with_dialect");
+
+            let doc = CSVDocument::parse_string_with_options(&line.to_string(), &options).unwrap();
+            assert_row(&doc.contents[0], cells, quoted, "parse_string_with_options");
+
+            let doc = CSVDocument::parse_bytes(line.as_bytes(), &options).unwrap();
+            assert_row(&doc.contents[0], cells, quoted, "parse_bytes");
+        }
+    }
+
+    #[test]
+    fn test_conformance_quote_escape() {
+        // An escape character equal to the quote reads exactly like doubled quotes.
+        let mut dialect = CSVDialect::new();
+        dialect.escape = Some('"');
+
+        for (line, cells, quoted) in LINE_CASES.iter() {
+            let row = CSVRow::parse_line_with_dialect(&line.to_string(), &dialect).unwrap();
+            assert_row(&row, cells, quoted, "parse_line_with_dialect");
+            assert_eq!(
+                row.to_string_with_dialect(&dialect),
+                row.to_string(),
+                "{:?}",
+                line
+            );
+        }
+
+        for line in ERROR_CASES.iter() {
+            assert_eq!(
+                CSVRow::parse_line_with_dialect(&line.to_string(), &dialect).err(),
+                Some("Unterminated quotes in cell.")
+            );
+        }
+    }
+
+    #[test]
+    fn test_conformance_errors() {
+        for line in ERROR_CASES.iter() {
+            let line = line.to_string();
+            let expected = "Unterminated quotes in cell.";
+
+            assert_eq!(CSVRow::parse_line_new(&line).err(), Some(expected));
+            assert_eq!(CSVRow::new().parse_line(&line).err(), Some(expected));
+            assert_eq!(CSVDocument::parse_string(&line).err(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_conformance_records() {
+        let cases: Vec<(&str, CSVLineTerminator, Vec<Vec<&str>>)> = vec![
+            (
+                "a,b\nc,d\n",
+                CSVLineTerminator::LF,
+                vec![vec!["a", "b"], vec!["c", "d"]],
+            ),
+            (
+                "a,b\nc,d",
+                CSVLineTerminator::LF,
+                vec![vec!["a", "b"], vec!["c", "d"]],
+            ),
+            (
+                "a,b\r\nc,d\r\n",
+                CSVLineTerminator::CRLF,
+                vec![vec!["a", "b"], vec!["c", "d"]],
+            ),
+            (
+                "a,b\rc,d",
+                CSVLineTerminator::CR,
+                vec![vec!["a", "b"], vec!["c", "d"]],
+            ),
+            ("a\r,b\r\n", CSVLineTerminator::CRLF, vec![vec!["a\r", "b"]]),
+            ("a,b\r\n", CSVLineTerminator::LF, vec![vec!["a", "b\r"]]),
+            (
+                "\"a\nb\",c\n",
+                CSVLineTerminator::LF,
+                vec![vec!["a\nb", "c"]],
+            ),
+            (
+                "\"a\r\nb\",c\r\n",
+                CSVLineTerminator::CRLF,
+                vec![vec!["a\r\nb", "c"]],
+            ),
+            (
+                "a,b\n\nc,d\n\n",
+                CSVLineTerminator::LF,
+                vec![vec!["a", "b"], vec!["c", "d"]],
+            ),
+            (
+                "\"\"\"a\nb\"\"\",c",
+                CSVLineTerminator::LF,
+                vec![vec!["\"a\nb\"", "c"]],
+            ),
+        ];
+
+        for (input, line_terminator, rows) in cases {
+            let mut options = CSVParseOptions::new();
+            options.dialect.has_header = false;
+            options.dialect.line_terminator = line_terminator;
+
+            let doc = CSVDocument::parse_string_with_options(&input.to_string(), &options)
+                .unwrap_or_else(|err| {
+                    panic!("{:?}: {}", input, err);
+                });
+            let cells: Vec<Vec<String>> = doc.contents.iter().map(|r| r.cells.clone()).collect();
+
+            assert_eq!(cells, rows, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenizer_tokens() {
+        let mut dialect = CSVDialect::new();
+        dialect.comment = Some('#');
+
+        let mut tokenizer = CSVTokenizer::new(&dialect);
+        let mut tokens = vec![];
+
+        for c in "#a,\"b\n\na,b\n#c".chars() {
+            if let Some(token) = tokenizer.push(c).unwrap() {
+                tokens.push(token);
+            }
+        }
+
+        tokens.push(tokenizer.finish().unwrap().unwrap());
+
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[0], CSVToken::Comment(String::from("a,\"b")));
+        assert_eq!(tokens[1], CSVToken::EmptyLine);
+        assert_eq!(tokens[3], CSVToken::Comment(String::from("c")));
+        assert_eq!(tokenizer.finish(), Ok(None));
+    }
+}
@@ -1,6 +1,60 @@
+mod csv_changeset;
+mod csv_columns;
+mod csv_concat;
+mod csv_dedup;
+mod csv_dialect;
+mod csv_diff;
 mod csv_document;
+mod csv_encoding;
+mod csv_external_sort;
+mod csv_filter;
+mod csv_group;
+mod csv_join;
+mod csv_options;
+mod csv_reader;
+mod csv_reshape;
 mod csv_row;
+mod csv_sniffer;
+mod csv_sort;
+mod csv_stats;
+mod csv_tokenizer;
 
+pub use self::csv_changeset::CSVChange;
+pub use self::csv_changeset::CSVChangeset;
+pub use self::csv_changeset::CSVConflict;
+pub use self::csv_concat::CSVAlign;
+pub use self::csv_dedup::CSVKeep;
+pub use self::csv_dialect::CSVDialect;
+pub use self::csv_dialect::CSVLineTerminator;
+pub use self::csv_dialect::CSVTrim;
+pub use self::csv_diff::CSVCellChange;
+pub use self::csv_diff::CSVDiff;
+pub use self::csv_diff::CSVRowChange;
+pub use self::csv_document::CSVComment;
 pub use self::csv_document::CSVDocument;
+pub use self::csv_encoding::CSVEncoding;
+pub use self::csv_external_sort::CSVExternalSort;
+pub use self::csv_filter::col;
+pub use self::csv_filter::CSVColumn;
+pub use self::csv_filter::CSVOperator;
+pub use self::csv_filter::CSVPredicate;
+pub use self::csv_group::CSVAggregation;
+pub use self::csv_join::CSVCollision;
+pub use self::csv_join::CSVJoinKind;
+pub use self::csv_join::CSVJoinOptions;
+pub use self::csv_options::CSVLimits;
+pub use self::csv_options::CSVParseOptions;
+pub use self::csv_options::CSVSanitize;
+pub use self::csv_options::CSVWidthPolicy;
+pub use self::csv_options::CSVWriteOptions;
+pub use self::csv_options::UTF8_BOM;
+pub use self::csv_reader::CSVReader;
 pub use self::csv_row::CSVParseable;
 pub use self::csv_row::CSVRow;
+pub use self::csv_sniffer::sniff;
+pub use self::csv_sniffer::CSVSniffResult;
+pub use self::csv_sort::CSVSortDirection;
+pub use self::csv_sort::CSVSortKey;
+pub use self::csv_sort::CSVSortMode;
+pub use self::csv_stats::CSVColumnStats;
+pub use self::csv_stats::CSVDescriber;